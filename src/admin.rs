@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+/// Only for callers whose token has [`crate::auth::Role::Admin`]; check with
+/// [`crate::auth::Claims::is_admin`].
 #[derive(Debug, Deserialize, Serialize)]
 pub struct CreateFlashSaleRequest {
     pub name: String,
@@ -0,0 +1,237 @@
+//! The API key format.
+//!
+//! A key is [`KEY_PREFIX`] followed by 104 lowercase hex digits: the key's id
+//! (32), the secret (64) and a CRC-32 of everything before it (8). For example
+//!
+//! ```text
+//! sup_<id: 32 hex><secret: 64 hex><crc32: 8 hex>
+//! ```
+//!
+//! The id lets the backend find the key's row without scanning, and the
+//! checksum lets [`parse`] turn away a truncated or mistyped key before any
+//! database lookup. The fixed prefix and shape are what [`DETECTION_REGEX`]
+//! matches, so secret scanners can flag a leaked key with almost no false
+//! positives.
+
+use crate::auth::GeneratedApiKey;
+use uuid::Uuid;
+
+pub const KEY_PREFIX: &str = "sup_";
+
+const ID_LEN: usize = 32;
+const SECRET_LEN: usize = 64;
+const CHECKSUM_LEN: usize = 8;
+const BODY_LEN: usize = ID_LEN + SECRET_LEN + CHECKSUM_LEN;
+
+/// Matches a whole key, for secret scanners such as GitHub's push protection.
+pub const DETECTION_REGEX: &str = r"\bsup_[0-9a-f]{104}\b";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiKeyFormatError {
+    /// Does not start with [`KEY_PREFIX`]: not one of our keys at all.
+    WrongPrefix,
+    WrongLength {
+        expected: usize,
+        actual: usize,
+    },
+    /// Anything but lowercase hex after the prefix.
+    InvalidCharacter {
+        position: usize,
+    },
+    /// Well-formed, but the checksum does not match: a typo or a truncated
+    /// copy.
+    ChecksumMismatch,
+}
+
+impl std::fmt::Display for ApiKeyFormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiKeyFormatError::WrongPrefix => {
+                write!(f, "API keys start with \"{}\"", KEY_PREFIX)
+            }
+            ApiKeyFormatError::WrongLength { expected, actual } => write!(
+                f,
+                "API key should be {} characters, got {}",
+                expected, actual
+            ),
+            ApiKeyFormatError::InvalidCharacter { position } => {
+                write!(f, "API key has an invalid character at {}", position)
+            }
+            ApiKeyFormatError::ChecksumMismatch => {
+                write!(f, "API key is mistyped or incomplete")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ApiKeyFormatError {}
+
+/// A key that passed [`parse`]. Whether it exists, and whether `secret`
+/// matches what was stored, is for the backend to decide.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedApiKey {
+    pub id: Uuid,
+    pub secret: String,
+}
+
+/// CRC-32 (IEEE 802.3, the zlib and PNG one), bitwise. Keys are short, so a
+/// lookup table would not pay for itself.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            let carry = crc & 1;
+            crc >>= 1;
+            if carry != 0 {
+                crc ^= 0xEDB8_8320;
+            }
+        }
+    }
+    !crc
+}
+
+fn encode(id: Uuid, secret: &str) -> String {
+    let mut key = format!("{}{}{}", KEY_PREFIX, id.simple(), secret);
+    let checksum = crc32(key.as_bytes());
+    key.push_str(&format!("{:08x}", checksum));
+    key
+}
+
+/// A fresh key. The secret is two v4 UUIDs' worth of randomness, 244 bits
+/// once their version and variant bits are discounted.
+pub fn generate(name: String) -> GeneratedApiKey {
+    let id = Uuid::new_v4();
+    let secret = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let full_key = encode(id, &secret);
+    GeneratedApiKey {
+        id,
+        key_preview: preview(&full_key),
+        full_key,
+        name,
+    }
+}
+
+/// Check a key's shape and checksum, and split out its id and secret.
+/// Surrounding whitespace, as pasted from a terminal, is ignored.
+pub fn parse(key: &str) -> Result<ParsedApiKey, ApiKeyFormatError> {
+    let key = key.trim();
+    let body = key
+        .strip_prefix(KEY_PREFIX)
+        .ok_or(ApiKeyFormatError::WrongPrefix)?;
+    if body.len() != BODY_LEN {
+        return Err(ApiKeyFormatError::WrongLength {
+            expected: KEY_PREFIX.len() + BODY_LEN,
+            actual: key.len(),
+        });
+    }
+    if let Some(position) = body
+        .bytes()
+        .position(|b| !matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    {
+        return Err(ApiKeyFormatError::InvalidCharacter {
+            position: KEY_PREFIX.len() + position,
+        });
+    }
+
+    let (signed, checksum) = key.split_at(key.len() - CHECKSUM_LEN);
+    let checksum = u32::from_str_radix(checksum, 16).expect("checked to be hex");
+    if crc32(signed.as_bytes()) != checksum {
+        return Err(ApiKeyFormatError::ChecksumMismatch);
+    }
+
+    let (id, secret) = body[..ID_LEN + SECRET_LEN].split_at(ID_LEN);
+    Ok(ParsedApiKey {
+        id: Uuid::parse_str(id).expect("32 hex digits are a simple UUID"),
+        secret: secret.to_string(),
+    })
+}
+
+/// What the dashboard shows in place of the key: the prefix and the last four
+/// characters. Those come from the checksum, so they give away next to
+/// nothing about the secret.
+pub fn preview(key: &str) -> String {
+    let key = key.trim();
+    let tail = key
+        .char_indices()
+        .rev()
+        .nth(3)
+        .map_or(key, |(i, _)| &key[i..]);
+    format!("{}...{}", KEY_PREFIX, tail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn generated_keys_parse_back() {
+        let generated = generate("ci".to_string());
+        assert_eq!(generated.full_key.len(), KEY_PREFIX.len() + BODY_LEN);
+        let parsed = parse(&format!(" {}\n", generated.full_key)).unwrap();
+        assert_eq!(parsed.id, generated.id);
+        assert_eq!(parsed.secret.len(), SECRET_LEN);
+        assert_eq!(
+            generated.key_preview,
+            format!(
+                "sup_...{}",
+                &generated.full_key[generated.full_key.len() - 4..]
+            )
+        );
+    }
+
+    #[test]
+    fn golden_key() {
+        // Checksum cross-checked against zlib's crc32.
+        let id = Uuid::parse_str("67e55044-10b1-426f-9247-bb680e5fe0c8").unwrap();
+        let key = encode(id, &"ab".repeat(32));
+        assert_eq!(
+            key,
+            "sup_67e5504410b1426f9247bb680e5fe0c8\
+             ababababababababababababababababababababababababababababababababc244c814"
+        );
+        assert_eq!(parse(&key).unwrap().id, id);
+    }
+
+    #[test]
+    fn rejects_mistyped_keys() {
+        let key = generate("ci".to_string()).full_key;
+
+        assert_eq!(parse("sk_live_abc"), Err(ApiKeyFormatError::WrongPrefix));
+        assert_eq!(
+            parse(&key[..key.len() - 1]),
+            Err(ApiKeyFormatError::WrongLength {
+                expected: 108,
+                actual: 107
+            })
+        );
+        let mut bad = key.clone();
+        bad.replace_range(10..11, "G");
+        assert_eq!(
+            parse(&bad),
+            Err(ApiKeyFormatError::InvalidCharacter { position: 10 })
+        );
+
+        // Every single-digit typo in the id or secret is caught.
+        for position in KEY_PREFIX.len()..key.len() - CHECKSUM_LEN {
+            let mut typo = key.clone().into_bytes();
+            typo[position] = if typo[position] == b'0' { b'1' } else { b'0' };
+            let typo = String::from_utf8(typo).unwrap();
+            assert_eq!(parse(&typo), Err(ApiKeyFormatError::ChecksumMismatch));
+        }
+    }
+
+    #[test]
+    fn detection_regex_describes_the_format() {
+        assert_eq!(
+            DETECTION_REGEX,
+            format!(r"\b{}[0-9a-f]{{{}}}\b", KEY_PREFIX, BODY_LEN)
+        );
+    }
+}
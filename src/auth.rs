@@ -1,4 +1,10 @@
+use crate::cidr::CidrBlock;
+use crate::error::ParseEnumError;
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::str::FromStr;
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -11,6 +17,167 @@ pub struct Claims {
     pub avatar: Option<String>,
     pub exp: usize, // expiration time
     pub iat: usize, // issued at
+    /// Not valid before, if later than `iat`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<usize>,
+    /// The service the token was issued for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    /// The service that issued the token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    /// The login session the token belongs to, so it can be revoked with it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
+    pub roles: HashSet<Role>,
+}
+
+/// Staff roles carried in the token. Customers have none.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// The admin routes: flash sales, user lookups and credit adjustments.
+    Admin,
+    /// Operates a platform integration on behalf of its linked users.
+    PlatformOwner,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClaimsError {
+    Expired {
+        expired_at: usize,
+    },
+    NotYetValid {
+        valid_from: usize,
+    },
+    /// Issued for another service, or without an audience after the
+    /// migration window.
+    WrongAudience {
+        actual: Option<String>,
+    },
+    /// Issued by another service, or without an issuer after the migration
+    /// window.
+    WrongIssuer {
+        actual: Option<String>,
+    },
+}
+
+impl std::fmt::Display for ClaimsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClaimsError::Expired { expired_at } => write!(f, "token expired at {}", expired_at),
+            ClaimsError::NotYetValid { valid_from } => {
+                write!(f, "token is not valid until {}", valid_from)
+            }
+            ClaimsError::WrongAudience { actual } => match actual {
+                Some(actual) => write!(f, "token was issued for {}", actual),
+                None => write!(f, "token has no audience"),
+            },
+            ClaimsError::WrongIssuer { actual } => match actual {
+                Some(actual) => write!(f, "token was issued by {}", actual),
+                None => write!(f, "token has no issuer"),
+            },
+        }
+    }
+}
+
+impl std::error::Error for ClaimsError {}
+
+fn timestamp(at: DateTime<Utc>) -> usize {
+    at.timestamp().max(0) as usize
+}
+
+impl Claims {
+    /// Claims for `sub` issued by `iss` at `now` and expiring `ttl` later. The
+    /// profile fields, session and roles start empty.
+    pub fn new(
+        sub: String,
+        email: String,
+        iss: String,
+        aud: String,
+        now: DateTime<Utc>,
+        ttl: Duration,
+    ) -> Self {
+        Self {
+            sub,
+            email,
+            discord_id: None,
+            username: None,
+            global_name: None,
+            avatar: None,
+            exp: timestamp(now + ttl),
+            iat: timestamp(now),
+            nbf: None,
+            aud: Some(aud),
+            iss: Some(iss),
+            sid: None,
+            roles: HashSet::new(),
+        }
+    }
+
+    /// The same claims reissued at `now` for another `ttl`.
+    pub fn renewed(&self, now: DateTime<Utc>, ttl: Duration) -> Self {
+        Self {
+            exp: timestamp(now + ttl),
+            iat: timestamp(now),
+            nbf: None,
+            ..self.clone()
+        }
+    }
+
+    /// Check the token's lifetime, issuer and audience. `leeway` allows for
+    /// clock skew between the issuer and this service, in both directions.
+    ///
+    /// Tokens minted before `aud` and `iss` were added carry neither. While
+    /// those are still in circulation, pass the time the new claims were
+    /// rolled out as `legacy_issued_before`: tokens issued (`iat`) before it
+    /// may lack either claim. Once the longest token lifetime has passed since
+    /// the rollout, pass `None` and a missing claim is rejected like a wrong
+    /// one. A claim that is present is always checked.
+    pub fn validate(
+        &self,
+        issuer: &str,
+        audience: &str,
+        legacy_issued_before: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+        leeway: Duration,
+    ) -> Result<(), ClaimsError> {
+        if timestamp(now - leeway) >= self.exp {
+            return Err(ClaimsError::Expired {
+                expired_at: self.exp,
+            });
+        }
+        if let Some(nbf) = self.nbf
+            && timestamp(now + leeway) < nbf
+        {
+            return Err(ClaimsError::NotYetValid { valid_from: nbf });
+        }
+        let legacy = legacy_issued_before.is_some_and(|cutoff| self.iat < timestamp(cutoff));
+        let matches = |claim: &Option<String>, expected: &str| match claim {
+            Some(actual) => actual == expected,
+            None => legacy,
+        };
+        if !matches(&self.iss, issuer) {
+            return Err(ClaimsError::WrongIssuer {
+                actual: self.iss.clone(),
+            });
+        }
+        if !matches(&self.aud, audience) {
+            return Err(ClaimsError::WrongAudience {
+                actual: self.aud.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role)
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.has_role(Role::Admin)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -48,6 +215,72 @@ pub struct ApiKeyResponse {
     pub id: String,
     pub name: String,
     pub key_preview: String,
+    /// The organization the key bills to, or `None` for a personal key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org_id: Option<String>,
+    /// `None` for a key created before scopes existed, which keeps the full
+    /// access it was issued with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<HashSet<ApiKeyScope>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Addresses the key may be used from. Empty allows any.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_cidrs: Vec<CidrBlock>,
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+    /// `None` if the key has never been used.
+    #[serde(default)]
+    pub last_used_at: Option<DateTime<Utc>>,
+    /// Requests made with the key, all time.
+    #[serde(default)]
+    pub request_count: u64,
+    /// Credits those requests used, all time.
+    #[serde(default)]
+    pub credits_consumed: i64,
+}
+
+/// One day of a key's usage, like [`crate::pricing::DailyUsageEntry`] for the
+/// whole account.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiKeyUsageEntry {
+    pub day: String,
+    pub requests: u64,
+    pub credits_used: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ApiKeyUsageQuery {
+    pub days: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyUsageResponse {
+    pub key_id: String,
+    pub entries: Vec<ApiKeyUsageEntry>,
+}
+
+impl ApiKeyResponse {
+    /// Whether a request for `operation` from `ip` may use this key at `now`.
+    pub fn check(
+        &self,
+        operation: ApiOperation,
+        ip: IpAddr,
+        now: DateTime<Utc>,
+    ) -> Result<(), ApiKeyRejection> {
+        if self.expires_at.is_some_and(|expires_at| expires_at <= now) {
+            return Err(ApiKeyRejection::Expired);
+        }
+        if !self.allowed_cidrs.is_empty()
+            && !self.allowed_cidrs.iter().any(|cidr| cidr.contains(ip))
+        {
+            return Err(ApiKeyRejection::AddressNotAllowed { ip });
+        }
+        if !scopes_permit(self.scopes.as_ref(), operation) {
+            return Err(ApiKeyRejection::MissingScope { operation });
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -67,8 +300,164 @@ pub struct DeleteApiKeyResponse {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateApiKeyRequest {
     pub name: String,
+    /// Scopes to grant. Omitted grants every scope, which is still less than
+    /// a legacy key: no scope allows [`ApiOperation::ManageAccount`]. Store
+    /// [`CreateApiKeyRequest::granted_scopes`], never this as it is, or the
+    /// new key would be a legacy one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<HashSet<ApiKeyScope>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_cidrs: Vec<CidrBlock>,
+    /// Create the key for this organization rather than the caller. Needs
+    /// [`crate::org::OrgPermission::ManageApiKeys`] there.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org_id: Option<String>,
+}
+
+impl CreateApiKeyRequest {
+    /// The scopes the new key gets, always an explicit set.
+    pub fn granted_scopes(&self) -> HashSet<ApiKeyScope> {
+        self.scopes.clone().unwrap_or_else(ApiKeyScope::all)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ApiKeyScope {
+    /// Text, image and username moderation.
+    #[serde(rename = "moderate")]
+    Moderate,
+    /// Video moderation. Separate from `moderate` because one clip can cost
+    /// as much as thousands of messages.
+    #[serde(rename = "moderate:video")]
+    ModerateVideo,
+    #[serde(rename = "usage:read")]
+    UsageRead,
+    #[serde(rename = "billing:read")]
+    BillingRead,
+    /// The platform API: provisioning and acting for linked users.
+    #[serde(rename = "platform")]
+    Platform,
+}
+
+impl ApiKeyScope {
+    pub fn all() -> HashSet<ApiKeyScope> {
+        HashSet::from([
+            ApiKeyScope::Moderate,
+            ApiKeyScope::ModerateVideo,
+            ApiKeyScope::UsageRead,
+            ApiKeyScope::BillingRead,
+            ApiKeyScope::Platform,
+        ])
+    }
 }
 
+impl FromStr for ApiKeyScope {
+    type Err = ParseEnumError;
+
+    fn from_str(input: &str) -> Result<ApiKeyScope, Self::Err> {
+        match input {
+            "moderate" => Ok(ApiKeyScope::Moderate),
+            "moderate:video" => Ok(ApiKeyScope::ModerateVideo),
+            "usage:read" => Ok(ApiKeyScope::UsageRead),
+            "billing:read" => Ok(ApiKeyScope::BillingRead),
+            "platform" => Ok(ApiKeyScope::Platform),
+            _ => Err(ParseEnumError::new(
+                input,
+                "ApiKeyScope",
+                &[
+                    "moderate",
+                    "moderate:video",
+                    "usage:read",
+                    "billing:read",
+                    "platform",
+                ],
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for ApiKeyScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiKeyScope::Moderate => write!(f, "moderate"),
+            ApiKeyScope::ModerateVideo => write!(f, "moderate:video"),
+            ApiKeyScope::UsageRead => write!(f, "usage:read"),
+            ApiKeyScope::BillingRead => write!(f, "billing:read"),
+            ApiKeyScope::Platform => write!(f, "platform"),
+        }
+    }
+}
+
+/// What an API request is trying to do, as far as key scopes care.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ApiOperation {
+    ModerateText,
+    ModerateImage,
+    ModerateUsername,
+    ModerateVideo,
+    ReadUsage,
+    ReadBilling,
+    Platform,
+    /// Changing the plan, API keys, or anything else about the account.
+    /// Only legacy unscoped keys can, and only so they keep working.
+    ManageAccount,
+}
+
+impl ApiOperation {
+    /// The scope that allows this operation, or `None` if no scope does.
+    pub fn required_scope(&self) -> Option<ApiKeyScope> {
+        match self {
+            ApiOperation::ModerateText
+            | ApiOperation::ModerateImage
+            | ApiOperation::ModerateUsername => Some(ApiKeyScope::Moderate),
+            ApiOperation::ModerateVideo => Some(ApiKeyScope::ModerateVideo),
+            ApiOperation::ReadUsage => Some(ApiKeyScope::UsageRead),
+            ApiOperation::ReadBilling => Some(ApiKeyScope::BillingRead),
+            ApiOperation::Platform => Some(ApiKeyScope::Platform),
+            ApiOperation::ManageAccount => None,
+        }
+    }
+}
+
+/// Whether a key with `scopes` may perform `operation`. `None` is a legacy
+/// key from before scopes, which may do anything.
+pub fn scopes_permit(scopes: Option<&HashSet<ApiKeyScope>>, operation: ApiOperation) -> bool {
+    match scopes {
+        None => true,
+        Some(scopes) => operation
+            .required_scope()
+            .is_some_and(|scope| scopes.contains(&scope)),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiKeyRejection {
+    Expired,
+    AddressNotAllowed { ip: IpAddr },
+    MissingScope { operation: ApiOperation },
+}
+
+impl std::fmt::Display for ApiKeyRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiKeyRejection::Expired => write!(f, "API key has expired"),
+            ApiKeyRejection::AddressNotAllowed { ip } => {
+                write!(f, "API key cannot be used from {}", ip)
+            }
+            ApiKeyRejection::MissingScope { operation } => match operation.required_scope() {
+                Some(scope) => write!(f, "API key is missing the {} scope", scope),
+                None => write!(f, "API keys cannot manage the account"),
+            },
+        }
+    }
+}
+
+impl std::error::Error for ApiKeyRejection {}
+
+/// A key as [`crate::api_key::generate`] makes it. `full_key` is shown once
+/// and never stored.
 #[derive(Debug)]
 pub struct GeneratedApiKey {
     pub id: Uuid,
@@ -145,12 +534,163 @@ pub struct GitHubEmail {
     pub visibility: Option<String>,
 }
 
+/// An OpenID Connect provider's `/.well-known/openid-configuration`, the
+/// subset we use.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcDiscoveryDocument {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    #[serde(default)]
+    pub userinfo_endpoint: Option<String>,
+    pub jwks_uri: String,
+    #[serde(default)]
+    pub end_session_endpoint: Option<String>,
+    #[serde(default)]
+    pub scopes_supported: Vec<String>,
+    #[serde(default)]
+    pub id_token_signing_alg_values_supported: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcTokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub id_token: String,
+    pub expires_in: Option<i64>,
+    pub refresh_token: Option<String>,
+    pub scope: Option<String>,
+}
+
+/// The `aud` claim, which the spec allows as one string or an array.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum OidcAudience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl OidcAudience {
+    pub fn contains(&self, client_id: &str) -> bool {
+        match self {
+            OidcAudience::One(aud) => aud == client_id,
+            OidcAudience::Many(auds) => auds.iter().any(|aud| aud == client_id),
+        }
+    }
+}
+
+/// The claims of a verified ID token. Checking the signature against the
+/// provider's `jwks_uri`, and `iss`, `aud` and `nonce` against the login, is
+/// the caller's job.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcIdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: OidcAudience,
+    pub exp: usize,
+    pub iat: usize,
+    #[serde(default)]
+    pub nonce: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    /// Default false, as for [`DiscordUser::verified`].
+    #[serde(default)]
+    pub email_verified: bool,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub preferred_username: Option<String>,
+    #[serde(default)]
+    pub picture: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthResponse {
     pub token: String,
     pub user: UserInfo,
     #[serde(default)]
     pub is_new_account: bool,
+    /// Exchanged at the refresh endpoint for a new `token` once it expires.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    /// Seconds until `token` expires.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_in: Option<i64>,
+}
+
+/// Refresh tokens are single use: each refresh returns a new one, and
+/// presenting a used one revokes the whole session.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshTokenRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefreshTokenResponse {
+    pub token: String,
+    pub refresh_token: String,
+    /// Seconds until `token` expires.
+    pub expires_in: i64,
+}
+
+/// A login, as listed on the account's sessions page. Every token issued
+/// for it carries its `id` as [`Claims::sid`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionResponse {
+    pub id: String,
+    pub provider: OAuthProviderType,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    /// From [`summarize_user_agent`], e.g. "Firefox on Windows".
+    pub user_agent: Option<String>,
+    /// Whether this is the session making the request.
+    #[serde(default)]
+    pub current: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevokeSessionResponse {
+    pub message: String,
+}
+
+/// Revoking every session but the current one, e.g. after a lost laptop.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevokeOtherSessionsResponse {
+    pub revoked: u32,
+}
+
+/// A short browser and OS description from a `User-Agent` header, for telling
+/// sessions apart. `None` if neither is recognised.
+pub fn summarize_user_agent(user_agent: &str) -> Option<String> {
+    // Order matters: Edge and Opera also claim Chrome, Chrome also claims
+    // Safari, and iOS also claims macOS.
+    let browser = [
+        ("Edg/", "Edge"),
+        ("OPR/", "Opera"),
+        ("Firefox/", "Firefox"),
+        ("Chrome/", "Chrome"),
+        ("Safari/", "Safari"),
+    ]
+    .into_iter()
+    .find(|(token, _)| user_agent.contains(token))
+    .map(|(_, name)| name);
+    let os = [
+        ("iPhone", "iOS"),
+        ("iPad", "iPadOS"),
+        ("Android", "Android"),
+        ("Windows", "Windows"),
+        ("Mac OS X", "macOS"),
+        ("CrOS", "ChromeOS"),
+        ("Linux", "Linux"),
+    ]
+    .into_iter()
+    .find(|(token, _)| user_agent.contains(token))
+    .map(|(_, name)| name);
+    match (browser, os) {
+        (Some(browser), Some(os)) => Some(format!("{} on {}", browser, os)),
+        (Some(name), None) | (None, Some(name)) => Some(name.to_string()),
+        (None, None) => None,
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -163,6 +703,141 @@ pub struct UserInfo {
     pub avatar: Option<String>,
 }
 
+/// What a provider tells us about the person logging in, whichever provider
+/// it is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExternalProfile {
+    pub provider: OAuthProviderType,
+    /// The ID token's `iss`, for OIDC only. The public providers have one
+    /// issuer each, so `provider` already says it.
+    pub issuer: Option<String>,
+    /// The provider's stable id for the person. For OIDC this is `sub`, which
+    /// is only unique per issuer: identify the person by `issuer` and this
+    /// together.
+    pub provider_user_id: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub username: String,
+    pub global_name: Option<String>,
+    pub avatar: Option<String>,
+}
+
+fn local_part(email: &str) -> &str {
+    email.split('@').next().unwrap_or(email)
+}
+
+impl ExternalProfile {
+    /// The email to match an existing account by. Only a verified email
+    /// counts, or anyone could claim an account by adding its address to a
+    /// provider that does not check.
+    ///
+    /// An OIDC provider is run by a customer, who can mark any address as
+    /// verified. Its emails only match if their domain is one of
+    /// `verified_domains`, the domains the organization that configured the
+    /// provider has proven it owns; the public providers ignore the list.
+    pub fn matching_email(&self, verified_domains: &[String]) -> Option<&str> {
+        let email = self.email.as_deref().filter(|_| self.email_verified)?;
+        if self.provider != OAuthProviderType::Oidc {
+            return Some(email);
+        }
+        let (_, domain) = email.rsplit_once('@')?;
+        verified_domains
+            .iter()
+            .any(|verified| verified.eq_ignore_ascii_case(domain))
+            .then_some(email)
+    }
+
+    /// The profile as the account `id` would show it. `None` without an
+    /// email, which every account needs.
+    pub fn user_info(&self, id: String) -> Option<UserInfo> {
+        Some(UserInfo {
+            id,
+            discord_id: (self.provider == OAuthProviderType::Discord)
+                .then(|| self.provider_user_id.clone()),
+            email: self.email.clone()?,
+            username: self.username.clone(),
+            global_name: self.global_name.clone(),
+            avatar: self.avatar.clone(),
+        })
+    }
+
+    /// GitHub's profile email is optional and may be stale, so prefer the
+    /// primary address from the emails endpoint.
+    pub fn github(user: GitHubUser, emails: &[GitHubEmail]) -> Self {
+        let primary = emails.iter().find(|email| email.primary && email.verified);
+        let email = primary.map(|email| email.email.clone()).or(user.email);
+        let email_verified = email.as_deref().is_some_and(|address| {
+            emails
+                .iter()
+                .any(|email| email.verified && email.email == address)
+        });
+        Self {
+            provider: OAuthProviderType::GitHub,
+            issuer: None,
+            provider_user_id: user.id.to_string(),
+            email,
+            email_verified,
+            username: user.login,
+            global_name: user.name,
+            avatar: user.avatar_url,
+        }
+    }
+}
+
+impl From<DiscordUser> for ExternalProfile {
+    fn from(user: DiscordUser) -> Self {
+        Self {
+            provider: OAuthProviderType::Discord,
+            issuer: None,
+            provider_user_id: user.id,
+            email: Some(user.email),
+            email_verified: user.verified,
+            username: user.username,
+            global_name: user.global_name,
+            avatar: user.avatar,
+        }
+    }
+}
+
+impl From<GoogleUser> for ExternalProfile {
+    fn from(user: GoogleUser) -> Self {
+        Self {
+            provider: OAuthProviderType::Google,
+            issuer: None,
+            provider_user_id: user.id,
+            username: local_part(&user.email).to_string(),
+            email: Some(user.email),
+            email_verified: user.verified_email,
+            global_name: user.name,
+            avatar: user.picture,
+        }
+    }
+}
+
+impl From<OidcIdTokenClaims> for ExternalProfile {
+    fn from(claims: OidcIdTokenClaims) -> Self {
+        let username = claims
+            .preferred_username
+            .or_else(|| {
+                claims
+                    .email
+                    .as_deref()
+                    .map(|email| local_part(email).to_string())
+            })
+            .unwrap_or_else(|| claims.sub.clone());
+        Self {
+            provider: OAuthProviderType::Oidc,
+            issuer: Some(claims.iss),
+            provider_user_id: claims.sub,
+            email: claims.email,
+            email_verified: claims.email_verified,
+            username,
+            global_name: claims.name,
+            avatar: claims.picture,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TokenRequest<'a> {
     pub client_id: &'a str,
@@ -172,11 +847,14 @@ pub struct TokenRequest<'a> {
     pub redirect_uri: &'a str,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum OAuthProviderType {
     Discord,
     Google,
     GitHub,
+    /// A customer's own OpenID Connect identity provider.
+    Oidc,
 }
 
 
@@ -186,6 +864,355 @@ impl OAuthProviderType {
             OAuthProviderType::Discord => "discord",
             OAuthProviderType::Google => "google",
             OAuthProviderType::GitHub => "github",
+            OAuthProviderType::Oidc => "oidc",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(scopes: Option<HashSet<ApiKeyScope>>) -> ApiKeyResponse {
+        ApiKeyResponse {
+            id: "key_1".to_string(),
+            name: "ci".to_string(),
+            key_preview: "sk_...abcd".to_string(),
+            org_id: None,
+            scopes,
+            expires_at: None,
+            allowed_cidrs: Vec::new(),
+            created_at: None,
+            last_used_at: None,
+            request_count: 0,
+            credits_consumed: 0,
+        }
+    }
+
+    fn now() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_760_000_000, 0).unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn scopes_gate_operations() {
+        let moderate_only = HashSet::from([ApiKeyScope::Moderate]);
+        assert!(scopes_permit(
+            Some(&moderate_only),
+            ApiOperation::ModerateImage
+        ));
+        assert!(!scopes_permit(
+            Some(&moderate_only),
+            ApiOperation::ModerateVideo
+        ));
+        assert!(!scopes_permit(
+            Some(&moderate_only),
+            ApiOperation::ReadBilling
+        ));
+
+        // Every scope still cannot manage the account; only legacy keys can.
+        assert!(!scopes_permit(
+            Some(&ApiKeyScope::all()),
+            ApiOperation::ManageAccount
+        ));
+        assert!(scopes_permit(None, ApiOperation::ManageAccount));
+
+        // A request without scopes still creates a scoped key.
+        let request = CreateApiKeyRequest {
+            name: "ci".to_string(),
+            scopes: None,
+            expires_at: None,
+            allowed_cidrs: Vec::new(),
+            org_id: None,
+        };
+        let granted = request.granted_scopes();
+        assert_eq!(granted, ApiKeyScope::all());
+        assert!(!scopes_permit(Some(&granted), ApiOperation::ManageAccount));
+    }
+
+    #[test]
+    fn check_applies_expiry_and_allowlist() {
+        let mut key = key(Some(HashSet::from([ApiKeyScope::UsageRead])));
+        key.allowed_cidrs = vec!["203.0.113.0/24".parse().unwrap()];
+        key.expires_at = Some(now() + chrono::Duration::days(1));
+
+        assert_eq!(
+            key.check(ApiOperation::ReadUsage, ip("203.0.113.9"), now()),
+            Ok(())
+        );
+        assert_eq!(
+            key.check(ApiOperation::ReadUsage, ip("198.51.100.1"), now()),
+            Err(ApiKeyRejection::AddressNotAllowed {
+                ip: ip("198.51.100.1")
+            })
+        );
+        assert_eq!(
+            key.check(ApiOperation::ModerateText, ip("203.0.113.9"), now()),
+            Err(ApiKeyRejection::MissingScope {
+                operation: ApiOperation::ModerateText
+            })
+        );
+        assert_eq!(
+            key.check(
+                ApiOperation::ReadUsage,
+                ip("203.0.113.9"),
+                now() + chrono::Duration::days(1)
+            ),
+            Err(ApiKeyRejection::Expired)
+        );
+    }
+
+    #[test]
+    fn claims_lifetime_and_audience() {
+        let leeway = Duration::seconds(30);
+        let claims = Claims {
+            nbf: Some(timestamp(now() + Duration::minutes(5))),
+            ..Claims::new(
+                "user_1".to_string(),
+                "a@example.com".to_string(),
+                "auth".to_string(),
+                "api".to_string(),
+                now(),
+                Duration::hours(1),
+            )
+        };
+        assert_eq!(claims.exp - claims.iat, 3600);
+
+        let valid_at = now() + Duration::minutes(10);
+        assert_eq!(
+            claims.validate("auth", "api", None, valid_at, leeway),
+            Ok(())
+        );
+        assert_eq!(
+            claims.validate("auth", "api", None, now(), leeway),
+            Err(ClaimsError::NotYetValid {
+                valid_from: claims.nbf.unwrap()
+            })
+        );
+        assert_eq!(
+            claims.validate(
+                "auth",
+                "api",
+                None,
+                now() + Duration::minutes(4) + leeway,
+                leeway
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            claims.validate("auth", "dashboard", None, valid_at, leeway),
+            Err(ClaimsError::WrongAudience {
+                actual: Some("api".to_string())
+            })
+        );
+
+        let expiry = now() + Duration::hours(1);
+        assert_eq!(claims.validate("auth", "api", None, expiry, leeway), Ok(()));
+        assert_eq!(
+            claims.validate("auth", "api", None, expiry + leeway, leeway),
+            Err(ClaimsError::Expired {
+                expired_at: claims.exp
+            })
+        );
+        assert_eq!(
+            claims.validate("billing", "api", None, valid_at, leeway),
+            Err(ClaimsError::WrongIssuer {
+                actual: Some("auth".to_string())
+            })
+        );
+        let renewed = claims.renewed(expiry, Duration::hours(1));
+        assert_eq!(
+            renewed.validate("auth", "api", None, expiry + leeway, leeway),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn claims_without_new_fields_still_parse() {
+        let claims: Claims = serde_json::from_str(
+            r#"{"sub": "user_1", "email": "a@example.com", "discord_id": null,
+                "username": null, "global_name": null, "avatar": null,
+                "exp": 1760003600, "iat": 1760000000}"#,
+        )
+        .unwrap();
+        assert!(!claims.is_admin());
+        assert_eq!(
+            claims.validate("auth", "api", None, now(), Duration::zero()),
+            Err(ClaimsError::WrongIssuer { actual: None })
+        );
+
+        // Inside the migration window only tokens issued before the rollout
+        // may leave the new claims out, and a present claim is still checked.
+        let rollout = now() + Duration::seconds(1);
+        assert_eq!(
+            claims.validate("auth", "api", Some(rollout), now(), Duration::zero()),
+            Ok(())
+        );
+        assert_eq!(
+            claims.validate("auth", "api", Some(now()), now(), Duration::zero()),
+            Err(ClaimsError::WrongIssuer { actual: None })
+        );
+        let other_service = Claims {
+            aud: Some("dashboard".to_string()),
+            ..claims.clone()
+        };
+        assert_eq!(
+            other_service.validate("auth", "api", Some(rollout), now(), Duration::zero()),
+            Err(ClaimsError::WrongAudience {
+                actual: Some("dashboard".to_string())
+            })
+        );
+
+        let admin: Claims = serde_json::from_str(
+            r#"{"sub": "user_2", "email": "b@example.com", "discord_id": null,
+                "username": null, "global_name": null, "avatar": null,
+                "exp": 1760003600, "iat": 1760000000, "roles": ["admin"]}"#,
+        )
+        .unwrap();
+        assert!(admin.is_admin());
+        assert!(!admin.has_role(Role::PlatformOwner));
+    }
+
+    #[test]
+    fn user_agent_summaries() {
+        for (user_agent, expected) in [
+            (
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/129.0.0.0 Safari/537.36 Edg/129.0.0.0",
+                Some("Edge on Windows"),
+            ),
+            (
+                "Mozilla/5.0 (iPhone; CPU iPhone OS 17_6 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.6 Mobile/15E148 Safari/604.1",
+                Some("Safari on iOS"),
+            ),
+            (
+                "Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0",
+                Some("Firefox on Linux"),
+            ),
+            (
+                "Mozilla/5.0 (Linux; Android 14) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/129.0.0.0 Mobile Safari/537.36",
+                Some("Chrome on Android"),
+            ),
+            ("curl/8.5.0", None),
+        ] {
+            assert_eq!(
+                summarize_user_agent(user_agent).as_deref(),
+                expected,
+                "{}",
+                user_agent
+            );
+        }
+    }
+
+    #[test]
+    fn providers_serialize_as_their_names() {
+        for provider in [
+            OAuthProviderType::Discord,
+            OAuthProviderType::Google,
+            OAuthProviderType::GitHub,
+            OAuthProviderType::Oidc,
+        ] {
+            assert_eq!(serde_json::to_value(provider).unwrap(), provider.as_str());
+        }
+    }
+
+    #[test]
+    fn github_profile_prefers_the_verified_primary_email() {
+        let user = GitHubUser {
+            id: 42,
+            login: "octo".to_string(),
+            email: Some("old@example.com".to_string()),
+            name: None,
+            avatar_url: None,
+        };
+        let email = |email: &str, primary, verified| GitHubEmail {
+            email: email.to_string(),
+            primary,
+            verified,
+            visibility: None,
+        };
+
+        let profile = ExternalProfile::github(
+            user.clone(),
+            &[
+                email("old@example.com", false, false),
+                email("octo@example.com", true, true),
+            ],
+        );
+        assert_eq!(profile.matching_email(&[]), Some("octo@example.com"));
+        assert_eq!(profile.provider_user_id, "42");
+
+        let profile = ExternalProfile::github(user, &[email("old@example.com", false, false)]);
+        assert_eq!(profile.email.as_deref(), Some("old@example.com"));
+        assert_eq!(profile.matching_email(&[]), None);
+    }
+
+    #[test]
+    fn oidc_emails_match_only_in_verified_domains() {
+        let profile = ExternalProfile {
+            provider: OAuthProviderType::Oidc,
+            issuer: Some("https://idp.acme.test".to_string()),
+            provider_user_id: "00u1".to_string(),
+            email: Some("ceo@victim.com".to_string()),
+            email_verified: true,
+            username: "ceo".to_string(),
+            global_name: None,
+            avatar: None,
+        };
+        // The IdP's say-so is not enough.
+        assert_eq!(profile.matching_email(&[]), None);
+        assert_eq!(profile.matching_email(&["acme.test".to_string()]), None);
+        assert_eq!(
+            profile.matching_email(&["Victim.com".to_string()]),
+            Some("ceo@victim.com")
+        );
+
+        // A public provider's verified email matches without a domain list.
+        let profile = ExternalProfile {
+            provider: OAuthProviderType::Google,
+            issuer: None,
+            ..profile
+        };
+        assert_eq!(profile.matching_email(&[]), Some("ceo@victim.com"));
+    }
+
+    #[test]
+    fn oidc_claims_map_to_user_info() {
+        let claims: OidcIdTokenClaims = serde_json::from_str(
+            r#"{"iss": "https://idp.example.com", "sub": "00u1", "aud": ["supervisor", "other"],
+                "exp": 1760003600, "iat": 1760000000, "email": "jo@example.com"}"#,
+        )
+        .unwrap();
+        assert!(claims.aud.contains("supervisor"));
+
+        let profile = ExternalProfile::from(claims);
+        assert_eq!(profile.issuer.as_deref(), Some("https://idp.example.com"));
+        // No `email_verified` claim: usable for the account, not for matching.
+        assert_eq!(profile.matching_email(&["example.com".to_string()]), None);
+        let user = profile.user_info("user_1".to_string()).unwrap();
+        assert_eq!(user.email, "jo@example.com");
+        assert_eq!(user.username, "jo");
+        assert_eq!(user.discord_id, None);
+
+        let profile = ExternalProfile {
+            email: None,
+            ..profile
+        };
+        assert_eq!(profile.user_info("user_1".to_string()), None);
+    }
+
+    #[test]
+    fn scopes_use_their_wire_names() {
+        let scopes: Vec<ApiKeyScope> =
+            serde_json::from_str(r#"["moderate:video", "billing:read"]"#).unwrap();
+        assert_eq!(
+            scopes,
+            vec![ApiKeyScope::ModerateVideo, ApiKeyScope::BillingRead]
+        );
+        for scope in ApiKeyScope::all() {
+            assert_eq!(scope.to_string().parse::<ApiKeyScope>(), Ok(scope));
         }
     }
 }
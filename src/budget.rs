@@ -0,0 +1,454 @@
+//! Warnings before a customer runs through their allowance or overdraft, and
+//! a hard cap on what the overdraft may cost in a month.
+//!
+//! The evaluator is pure: it takes the last-notified state, and a source of
+//! notification ids, and returns the notifications to send along with the
+//! state to store, so the backend job and its tests agree on exactly when an
+//! alert fires.
+
+use crate::money::{Currency, Money};
+use crate::notifications::NotificationResponse;
+use crate::pricing::{
+    CREDITS_PER_PENNY, CreditsInfoResponse, OverdraftSettingsResponse, credits_to_gbp,
+    format_credits, format_credits_as_gbp,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+pub const BUDGET_ALERT_NOTIFICATION_TYPE: &str = "budget_alert";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BudgetSettingsError {
+    /// A threshold outside 1 to 100 percent.
+    ThresholdOutOfRange { percent: u8 },
+    /// The overdraft is billed in GBP, so a cap in anything else could only be
+    /// enforced at a guessed exchange rate.
+    CapCurrency { currency: Currency },
+    /// Zero or less. No cap is `None`, not a cap of nothing.
+    CapNotPositive,
+}
+
+impl std::fmt::Display for BudgetSettingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BudgetSettingsError::ThresholdOutOfRange { percent } => {
+                write!(f, "alert threshold {}% is not between 1% and 100%", percent)
+            }
+            BudgetSettingsError::CapCurrency { currency } => {
+                write!(f, "spend cap must be in gbp, not {}", currency)
+            }
+            BudgetSettingsError::CapNotPositive => write!(f, "spend cap must be more than zero"),
+        }
+    }
+}
+
+impl std::error::Error for BudgetSettingsError {}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BudgetAlertSettings {
+    /// Percentages of the monthly allocation used that trigger an alert.
+    #[serde(default = "default_thresholds")]
+    pub allocation_thresholds: Vec<u8>,
+    /// Percentages of the overdraft limit used that trigger an alert.
+    #[serde(default = "default_thresholds")]
+    pub overdraft_thresholds: Vec<u8>,
+    /// Most the overdraft may cost in one calendar month. Overdraft use stops
+    /// here even if the credit limit is higher. Must be more than zero.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monthly_spend_cap: Option<Money>,
+}
+
+fn default_thresholds() -> Vec<u8> {
+    vec![50, 80, 100]
+}
+
+impl Default for BudgetAlertSettings {
+    fn default() -> Self {
+        Self {
+            allocation_thresholds: default_thresholds(),
+            overdraft_thresholds: default_thresholds(),
+            monthly_spend_cap: None,
+        }
+    }
+}
+
+/// The highest alert already sent for each measure. Stored per customer
+/// between evaluations.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BudgetAlertState {
+    #[serde(default)]
+    pub allocation_percent: u8,
+    #[serde(default)]
+    pub overdraft_percent: u8,
+    #[serde(default)]
+    pub spend_cap_reached: bool,
+}
+
+impl BudgetAlertSettings {
+    /// Check settings the way the settings endpoint should before saving.
+    pub fn validate(&self) -> Result<(), BudgetSettingsError> {
+        let thresholds = self
+            .allocation_thresholds
+            .iter()
+            .chain(&self.overdraft_thresholds);
+        for &percent in thresholds {
+            if !(1..=100).contains(&percent) {
+                return Err(BudgetSettingsError::ThresholdOutOfRange { percent });
+            }
+        }
+        if let Some(cap) = &self.monthly_spend_cap {
+            if cap.currency != Currency::Gbp {
+                return Err(BudgetSettingsError::CapCurrency {
+                    currency: cap.currency.clone(),
+                });
+            }
+            if cap.is_negative() || cap.is_zero() {
+                return Err(BudgetSettingsError::CapNotPositive);
+            }
+        }
+        Ok(())
+    }
+
+    /// The spend cap, if it is one that can be enforced. A zero or non-GBP cap
+    /// that slipped past [`BudgetAlertSettings::validate`] counts as none.
+    fn enforced_cap(&self) -> Option<&Money> {
+        self.monthly_spend_cap
+            .as_ref()
+            .filter(|cap| cap.currency == Currency::Gbp && cap.minor > 0)
+    }
+
+    /// Most overdraft the customer may have outstanding now: the credit
+    /// limit, or less if the spend cap runs out first. Overdraft drawn and
+    /// already settled this month still counts against the cap, so settling
+    /// mid-month does not make the whole cap available again.
+    pub fn effective_overdraft_limit(&self, overdraft: &OverdraftSettingsResponse) -> i64 {
+        match self.enforced_cap() {
+            Some(cap) => {
+                let settled = (overdraft.spent_this_month - overdraft.used).max(0);
+                let cap_credits = cap.minor.saturating_mul(CREDITS_PER_PENNY);
+                overdraft.limit.min((cap_credits - settled).max(0))
+            }
+            _ => overdraft.limit,
+        }
+    }
+
+    /// Credits of overdraft the customer may still draw.
+    pub fn overdraft_headroom(&self, overdraft: &OverdraftSettingsResponse) -> i64 {
+        (self.effective_overdraft_limit(overdraft) - overdraft.used).max(0)
+    }
+
+    /// Notifications due now, and the state to store for next time. Each
+    /// notification's id comes from `new_id`.
+    ///
+    /// Only the highest threshold newly crossed alerts, so jumping from 40% to
+    /// 90% sends one 80% alert rather than two. A measure that falls back
+    /// below what was notified, because the period reset or the overdraft was
+    /// settled, re-arms its alerts.
+    pub fn evaluate(
+        &self,
+        info: &CreditsInfoResponse,
+        overdraft: &OverdraftSettingsResponse,
+        state: &BudgetAlertState,
+        now: DateTime<Utc>,
+        mut new_id: impl FnMut() -> String,
+    ) -> (Vec<NotificationResponse>, BudgetAlertState) {
+        let mut notifications = Vec::new();
+
+        let allocation = percent(info.used_this_month, info.monthly_allocation);
+        let allocation_percent = highest_crossed(&self.allocation_thresholds, allocation);
+        if allocation_percent > state.allocation_percent {
+            notifications.push(notification(
+                new_id(),
+                format!("{}% of monthly credits used", allocation_percent),
+                format!(
+                    "You have used {} of your {} monthly credits.",
+                    format_credits(info.used_this_month),
+                    format_credits(info.monthly_allocation)
+                ),
+                allocation_percent >= 100,
+                now,
+            ));
+        }
+
+        let limit = self.effective_overdraft_limit(overdraft);
+        let used = if overdraft.enabled { overdraft.used } else { 0 };
+        let overdraft_percent = highest_crossed(&self.overdraft_thresholds, percent(used, limit));
+        if overdraft_percent > state.overdraft_percent {
+            notifications.push(notification(
+                new_id(),
+                format!("{}% of overdraft used", overdraft_percent),
+                format!(
+                    "You have used {} of your {} overdraft.",
+                    format_credits_as_gbp(used),
+                    format_credits_as_gbp(limit)
+                ),
+                overdraft_percent >= 100,
+                now,
+            ));
+        }
+
+        let spent = if overdraft.enabled {
+            overdraft.spent_this_month
+        } else {
+            0
+        };
+        let spend_cap_reached = self
+            .enforced_cap()
+            .is_some_and(|cap| credits_to_gbp(spent).minor >= cap.minor);
+        if spend_cap_reached && !state.spend_cap_reached {
+            let cap = self.enforced_cap().map(Money::format_compact);
+            notifications.push(notification(
+                new_id(),
+                "Monthly spend cap reached".to_string(),
+                format!(
+                    "Overdraft use has reached your {} monthly cap and is paused until it resets.",
+                    cap.unwrap_or_default()
+                ),
+                true,
+                now,
+            ));
+        }
+        let next = BudgetAlertState {
+            allocation_percent,
+            overdraft_percent,
+            spend_cap_reached,
+        };
+        (notifications, next)
+    }
+}
+
+/// `used` as a whole percentage of `total`, rounded down. Nothing to measure
+/// against counts as 0%.
+fn percent(used: i64, total: i64) -> u8 {
+    if total <= 0 || used <= 0 {
+        return 0;
+    }
+    (i128::from(used) * 100 / i128::from(total)).min(255) as u8
+}
+
+fn highest_crossed(thresholds: &[u8], percent: u8) -> u8 {
+    thresholds
+        .iter()
+        .copied()
+        .filter(|threshold| *threshold <= percent)
+        .max()
+        .unwrap_or(0)
+}
+
+fn notification(
+    id: String,
+    title: String,
+    message: String,
+    is_important: bool,
+    now: DateTime<Utc>,
+) -> NotificationResponse {
+    NotificationResponse {
+        id,
+        title,
+        message,
+        notification_type: BUDGET_ALERT_NOTIFICATION_TYPE.to_string(),
+        created_at: now.to_rfc3339(),
+        is_read: false,
+        is_important,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_760_000_000, 0).unwrap()
+    }
+
+    fn usage(used_this_month: i64) -> CreditsInfoResponse {
+        CreditsInfoResponse {
+            balance: 0,
+            monthly_allocation: 1_000_000,
+            used_this_month,
+            remaining_this_month: 0,
+            usage_percentage: 0.0,
+            reset_date: None,
+            extra_credits: 0,
+            discount_available: false,
+            discount_expires_at: None,
+            discount_percentage: None,
+            rate_limited: false,
+            rate_limit_resets_at: None,
+            trial_available: false,
+            buckets: Vec::new(),
+        }
+    }
+
+    fn overdraft(used: i64) -> OverdraftSettingsResponse {
+        OverdraftSettingsResponse {
+            enabled: true,
+            limit: 7_500_000,
+            used,
+            spent_this_month: used,
+        }
+    }
+
+    fn id() -> String {
+        "n".to_string()
+    }
+
+    fn titles(notifications: &[NotificationResponse]) -> Vec<&str> {
+        notifications.iter().map(|n| n.title.as_str()).collect()
+    }
+
+    #[test]
+    fn alerts_once_per_threshold_and_only_the_highest() {
+        let settings = BudgetAlertSettings::default();
+        let state = BudgetAlertState::default();
+
+        let (sent, state) = settings.evaluate(&usage(400_000), &overdraft(0), &state, now(), id);
+        assert!(sent.is_empty());
+
+        let (sent, state) = settings.evaluate(&usage(900_000), &overdraft(0), &state, now(), id);
+        assert_eq!(titles(&sent), vec!["80% of monthly credits used"]);
+        assert_eq!(
+            sent[0].message,
+            "You have used 900,000 of your 1,000,000 monthly credits."
+        );
+        assert!(!sent[0].is_important);
+        assert_eq!(sent[0].notification_type, BUDGET_ALERT_NOTIFICATION_TYPE);
+
+        let (sent, state) = settings.evaluate(&usage(950_000), &overdraft(0), &state, now(), id);
+        assert!(sent.is_empty());
+
+        let (sent, state) =
+            settings.evaluate(&usage(1_000_000), &overdraft(3_750_000), &state, now(), id);
+        assert_eq!(
+            titles(&sent),
+            vec!["100% of monthly credits used", "50% of overdraft used"]
+        );
+        assert!(sent[0].is_important);
+
+        // A new period re-arms the allocation alerts.
+        let (_, state) = settings.evaluate(&usage(0), &overdraft(3_750_000), &state, now(), id);
+        let (sent, _) =
+            settings.evaluate(&usage(500_000), &overdraft(3_750_000), &state, now(), id);
+        assert_eq!(titles(&sent), vec!["50% of monthly credits used"]);
+    }
+
+    #[test]
+    fn spend_cap_shrinks_the_overdraft_and_alerts() {
+        let settings = BudgetAlertSettings {
+            monthly_spend_cap: Some(Money::gbp(500)),
+            ..Default::default()
+        };
+        assert_eq!(settings.effective_overdraft_limit(&overdraft(0)), 3_750_000);
+        let small = OverdraftSettingsResponse {
+            limit: 1_000,
+            ..overdraft(0)
+        };
+        assert_eq!(settings.effective_overdraft_limit(&small), 1_000);
+
+        let (sent, state) = settings.evaluate(
+            &usage(0),
+            &overdraft(3_750_000),
+            &BudgetAlertState::default(),
+            now(),
+            id,
+        );
+        assert_eq!(
+            titles(&sent),
+            vec!["100% of overdraft used", "Monthly spend cap reached"]
+        );
+        assert!(state.spend_cap_reached);
+        assert!(sent[1].message.contains("£5"));
+
+        // The cap is on what was drawn this month, even if it has since been
+        // settled.
+        let settled = OverdraftSettingsResponse {
+            used: 0,
+            ..overdraft(3_750_000)
+        };
+        let mut next = 0;
+        let (sent, _) = settings.evaluate(
+            &usage(0),
+            &settled,
+            &BudgetAlertState::default(),
+            now(),
+            || {
+                next += 1;
+                format!("n{}", next)
+            },
+        );
+        assert_eq!(titles(&sent), vec!["Monthly spend cap reached"]);
+        assert_eq!(sent[0].id, "n1");
+    }
+
+    #[test]
+    fn settling_mid_month_does_not_reset_the_cap() {
+        let settings = BudgetAlertSettings {
+            monthly_spend_cap: Some(Money::gbp(500)),
+            ..Default::default()
+        };
+        // £4 of the £5 cap drawn, then settled.
+        let settled = OverdraftSettingsResponse {
+            used: 0,
+            ..overdraft(3_000_000)
+        };
+        assert_eq!(settings.effective_overdraft_limit(&settled), 750_000);
+        assert_eq!(settings.overdraft_headroom(&settled), 750_000);
+
+        // Drawing the last £1 uses the cap up until the month ends.
+        let drawn = OverdraftSettingsResponse {
+            used: 750_000,
+            spent_this_month: 3_750_000,
+            ..settled
+        };
+        assert_eq!(settings.overdraft_headroom(&drawn), 0);
+        let settled_again = OverdraftSettingsResponse { used: 0, ..drawn };
+        assert_eq!(settings.overdraft_headroom(&settled_again), 0);
+
+        // Without a cap only the credit limit applies.
+        let uncapped = BudgetAlertSettings::default();
+        assert_eq!(uncapped.overdraft_headroom(&settled_again), 7_500_000);
+    }
+
+    #[test]
+    fn a_zero_cap_is_refused_and_never_fires() {
+        let settings = BudgetAlertSettings {
+            monthly_spend_cap: Some(Money::gbp(0)),
+            ..Default::default()
+        };
+        assert_eq!(
+            settings.validate(),
+            Err(BudgetSettingsError::CapNotPositive)
+        );
+        assert_eq!(settings.effective_overdraft_limit(&overdraft(0)), 7_500_000);
+        let (sent, state) = settings.evaluate(
+            &usage(0),
+            &overdraft(0),
+            &BudgetAlertState::default(),
+            now(),
+            id,
+        );
+        assert!(sent.is_empty());
+        assert!(!state.spend_cap_reached);
+    }
+
+    #[test]
+    fn validation() {
+        assert_eq!(BudgetAlertSettings::default().validate(), Ok(()));
+        let settings = BudgetAlertSettings {
+            overdraft_thresholds: vec![0],
+            ..Default::default()
+        };
+        assert_eq!(
+            settings.validate(),
+            Err(BudgetSettingsError::ThresholdOutOfRange { percent: 0 })
+        );
+        let settings = BudgetAlertSettings {
+            monthly_spend_cap: Some(Money::new(500, Currency::Usd)),
+            ..Default::default()
+        };
+        assert!(matches!(
+            settings.validate(),
+            Err(BudgetSettingsError::CapCurrency { .. })
+        ));
+    }
+}
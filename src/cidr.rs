@@ -0,0 +1,201 @@
+//! CIDR blocks for API key allowlists, on `std::net` alone.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::Display;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CidrError {
+    InvalidAddress {
+        input: String,
+    },
+    /// Longer than the address (32 for IPv4, 128 for IPv6), or not a number.
+    InvalidPrefix {
+        input: String,
+    },
+    /// Bits set past the prefix, as in `10.0.0.1/8`. Usually a typo for a
+    /// narrower block, so it is refused rather than silently widened.
+    HostBitsSet {
+        input: String,
+    },
+}
+
+impl std::fmt::Display for CidrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CidrError::InvalidAddress { input } => write!(f, "\"{}\" is not an IP address", input),
+            CidrError::InvalidPrefix { input } => {
+                write!(f, "\"{}\" has an invalid prefix length", input)
+            }
+            CidrError::HostBitsSet { input } => write!(
+                f,
+                "\"{}\" has bits set past its prefix; did you mean a narrower block?",
+                input
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CidrError {}
+
+/// An address block such as `203.0.113.0/24` or `2001:db8::/32`. A bare
+/// address is a block of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CidrBlock {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+fn to_bits(addr: IpAddr) -> u128 {
+    match addr {
+        IpAddr::V4(v4) => u128::from(u32::from(v4)),
+        IpAddr::V6(v6) => u128::from(v6),
+    }
+}
+
+fn width(addr: IpAddr) -> u8 {
+    if addr.is_ipv4() { 32 } else { 128 }
+}
+
+fn mask(addr: IpAddr, prefix: u8) -> u128 {
+    let width = width(addr);
+    let all = if width == 32 {
+        u128::from(u32::MAX)
+    } else {
+        u128::MAX
+    };
+    match prefix {
+        0 => 0,
+        _ => all & !((1u128 << (width - prefix)) - 1),
+    }
+}
+
+impl CidrBlock {
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Whether `ip` is in the block. IPv4-mapped IPv6 addresses
+    /// (`::ffff:203.0.113.7`) count as the IPv4 address they carry, since
+    /// that is how dual-stack listeners report IPv4 clients.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        if ip.is_ipv4() != self.addr.is_ipv4() {
+            return false;
+        }
+        let mask = mask(self.addr, self.prefix);
+        to_bits(ip) & mask == to_bits(self.addr) & mask
+    }
+}
+
+impl FromStr for CidrBlock {
+    type Err = CidrError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match input.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (input.trim(), None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| CidrError::InvalidAddress {
+            input: input.to_string(),
+        })?;
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= width(addr))
+                .ok_or_else(|| CidrError::InvalidPrefix {
+                    input: input.to_string(),
+                })?,
+            None => width(addr),
+        };
+        if to_bits(addr) & !mask(addr, prefix) != 0 {
+            return Err(CidrError::HostBitsSet {
+                input: input.to_string(),
+            });
+        }
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl Display for CidrBlock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl Serialize for CidrBlock {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for CidrBlock {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(s: &str) -> CidrBlock {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn membership() {
+        let office = block("203.0.113.0/24");
+        assert!(office.contains(ip("203.0.113.0")));
+        assert!(office.contains(ip("203.0.113.255")));
+        assert!(!office.contains(ip("203.0.114.0")));
+        assert!(office.contains(ip("::ffff:203.0.113.7")));
+        assert!(!office.contains(ip("2001:db8::1")));
+
+        assert!(block("2001:db8::/32").contains(ip("2001:db8:ffff::1")));
+        assert!(!block("2001:db8::/32").contains(ip("2001:db9::1")));
+        assert!(block("0.0.0.0/0").contains(ip("8.8.8.8")));
+        assert!(block("198.51.100.7").contains(ip("198.51.100.7")));
+        assert!(!block("198.51.100.7").contains(ip("198.51.100.8")));
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!(block("198.51.100.7").to_string(), "198.51.100.7/32");
+        assert_eq!(block(" 2001:db8::/32 ").to_string(), "2001:db8::/32");
+        for (input, expected) in [
+            (
+                "10.0.0.1/8",
+                CidrError::HostBitsSet {
+                    input: "10.0.0.1/8".to_string(),
+                },
+            ),
+            (
+                "10.0.0.0/33",
+                CidrError::InvalidPrefix {
+                    input: "10.0.0.0/33".to_string(),
+                },
+            ),
+            (
+                "example.com/8",
+                CidrError::InvalidAddress {
+                    input: "example.com/8".to_string(),
+                },
+            ),
+        ] {
+            assert_eq!(input.parse::<CidrBlock>(), Err(expected));
+        }
+    }
+}
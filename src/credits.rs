@@ -1,4 +1,8 @@
+use crate::money::Money;
+use crate::pricing::{CreditBalance, CreditTransaction, CreditTransactionKind};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CreditProductResponse {
@@ -6,8 +10,8 @@ pub struct CreditProductResponse {
     pub price_id: String,
     pub name: String,
     pub description: Option<String>,
-    pub price_cents: i64,
-    pub currency: String,
+    #[serde(flatten, with = "crate::money::price_cents_and_currency")]
+    pub price: Money,
     pub credits_amount: i64,
 }
 
@@ -20,3 +24,458 @@ pub struct BuyCreditsRequest {
 pub struct BuyCreditsResponse {
     pub checkout_url: String,
 }
+
+/// Where a bucket of extra credits came from. Sources differ in how long their
+/// credits last: purchased packs usually never expire, promotions and referral
+/// rewards can.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CreditSource {
+    Purchase,
+    ReferralReward,
+    Promotion,
+    AdminAdjustment,
+}
+
+/// Credits held on top of the monthly allowance. `extra_credits` is the sum
+/// of the live buckets.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CreditBucket {
+    pub id: String,
+    pub source: CreditSource,
+    /// Credits left in the bucket.
+    pub amount: i64,
+    /// `None` never expires.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl CreditBucket {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// Credits still usable across `buckets` at `now`.
+pub fn live_credits(buckets: &[CreditBucket], now: DateTime<Utc>) -> i64 {
+    buckets
+        .iter()
+        .filter(|bucket| !bucket.is_expired(now))
+        .map(|bucket| bucket.amount.max(0))
+        .sum()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BucketDraw {
+    pub bucket_id: String,
+    pub amount: i64,
+}
+
+/// How a charge was paid for.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Consumption {
+    pub from_allowance: i64,
+    /// In the order the buckets were drawn from.
+    pub from_buckets: Vec<BucketDraw>,
+    /// What the allowance and every live bucket could not cover, for the
+    /// overdraft to pick up or the request to be refused.
+    pub shortfall: i64,
+}
+
+/// Split a charge of `amount` credits between the remaining monthly allowance
+/// and `buckets`, without changing either.
+///
+/// The allowance goes first, because it is lost at the reset anyway. Buckets
+/// follow soonest expiry first, so credits are never left to expire while
+/// longer-lived ones are spent; buckets that never expire come last, and ties
+/// keep the order they were given in. Expired buckets are skipped.
+pub fn consume(
+    allowance_remaining: i64,
+    buckets: &[CreditBucket],
+    amount: i64,
+    now: DateTime<Utc>,
+) -> Consumption {
+    let amount = amount.max(0);
+    let from_allowance = amount.min(allowance_remaining.max(0));
+    let mut left = amount - from_allowance;
+
+    let mut live: Vec<&CreditBucket> = buckets
+        .iter()
+        .filter(|bucket| !bucket.is_expired(now) && bucket.amount > 0)
+        .collect();
+    live.sort_by_key(|bucket| (bucket.expires_at.is_none(), bucket.expires_at));
+
+    let mut from_buckets = Vec::new();
+    for bucket in live {
+        if left == 0 {
+            break;
+        }
+        let drawn = left.min(bucket.amount);
+        from_buckets.push(BucketDraw {
+            bucket_id: bucket.id.clone(),
+            amount: drawn,
+        });
+        left -= drawn;
+    }
+
+    Consumption {
+        from_allowance,
+        from_buckets,
+        shortfall: left,
+    }
+}
+
+/// Credits used per API key across `transactions`, refunds netted off. Usage
+/// not made through a key is left out.
+pub fn usage_by_api_key(transactions: &[CreditTransaction]) -> HashMap<String, i64> {
+    let mut usage = HashMap::new();
+    for transaction in transactions {
+        if let Some(key_id) = &transaction.api_key_id
+            && transaction.transaction_type.counts_as_usage()
+        {
+            *usage.entry(key_id.clone()).or_insert(0) -= transaction.amount;
+        }
+    }
+    usage
+}
+
+/// Where the ledger and the balance disagree. Each one points support at the
+/// line, or the total, to look at.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "discrepancy", rename_all = "snake_case")]
+pub enum LedgerDiscrepancy {
+    /// An entry's `balance_after` does not follow from the last recorded
+    /// balance and the amounts since.
+    RunningBalance {
+        transaction_id: String,
+        expected: i64,
+        recorded: i64,
+    },
+    /// The ledger's closing balance is not the remaining allowance plus extra
+    /// credits. Only reported when the ledger cannot split the two.
+    Balance { ledger: i64, reported: i64 },
+    /// Usage since the latest monthly grant is not `used_current_period`.
+    PeriodUsage { ledger: i64, reported: i64 },
+    /// The allowance left after this period's usage is not
+    /// `remaining_credits`.
+    Allowance { ledger: i64, reported: i64 },
+    /// The rest of the closing balance is not `extra_credits`.
+    ExtraCredits { ledger: i64, reported: i64 },
+}
+
+/// Check `transactions` against `balance`. An empty result means the ledger
+/// explains the balance.
+///
+/// Entries are taken in `created_at` order. The running balance is carried
+/// across entries without a `balance_after`, so the next one that has it is
+/// checked against the last recorded balance plus every amount since. After a
+/// mismatch the recorded figure is trusted again, so one bad entry is
+/// reported once.
+///
+/// When the latest monthly grant is in `transactions`, the period's usage is
+/// known, and with it how the closing balance splits between allowance and
+/// extra credits: usage draws on the allowance first, as in [`consume`].
+/// Each part is then checked on its own. Without the grant only the total can
+/// be.
+pub fn reconcile(
+    balance: &CreditBalance,
+    transactions: &[CreditTransaction],
+) -> Vec<LedgerDiscrepancy> {
+    let mut ordered: Vec<&CreditTransaction> = transactions.iter().collect();
+    ordered.sort_by_key(|transaction| transaction.created_at);
+    let mut discrepancies = Vec::new();
+
+    let mut running: Option<i64> = None;
+    for transaction in &ordered {
+        let expected = running.map(|running| running + transaction.amount);
+        if let (Some(expected), Some(recorded)) = (expected, transaction.balance_after)
+            && expected != recorded
+        {
+            discrepancies.push(LedgerDiscrepancy::RunningBalance {
+                transaction_id: transaction.id.clone(),
+                expected,
+                recorded,
+            });
+        }
+        running = transaction.balance_after.or(expected);
+    }
+
+    let grant = ordered.iter().rposition(|transaction| {
+        transaction.transaction_type == CreditTransactionKind::MonthlyGrant
+    });
+    let Some(grant) = grant else {
+        if let Some(ledger) = running {
+            let reported = balance.remaining_credits + balance.extra_credits;
+            if ledger != reported {
+                discrepancies.push(LedgerDiscrepancy::Balance { ledger, reported });
+            }
+        }
+        return discrepancies;
+    };
+
+    let usage = -ordered[grant + 1..]
+        .iter()
+        .filter(|transaction| transaction.transaction_type.counts_as_usage())
+        .map(|transaction| transaction.amount)
+        .sum::<i64>();
+    if usage != balance.used_current_period {
+        discrepancies.push(LedgerDiscrepancy::PeriodUsage {
+            ledger: usage,
+            reported: balance.used_current_period,
+        });
+    }
+    if let Some(total) = running {
+        let allowance = (balance.max_monthly_credits - usage).max(0);
+        if allowance != balance.remaining_credits {
+            discrepancies.push(LedgerDiscrepancy::Allowance {
+                ledger: allowance,
+                reported: balance.remaining_credits,
+            });
+        }
+        let extra = total - allowance;
+        if extra != balance.extra_credits {
+            discrepancies.push(LedgerDiscrepancy::ExtraCredits {
+                ledger: extra,
+                reported: balance.extra_credits,
+            });
+        }
+    }
+
+    discrepancies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn entry(
+        id: &str,
+        kind: CreditTransactionKind,
+        amount: i64,
+        balance_after: Option<i64>,
+    ) -> CreditTransaction {
+        let minute = id.parse::<u32>().unwrap();
+        CreditTransaction {
+            id: id.to_string(),
+            amount,
+            transaction_type: kind,
+            balance_after,
+            api_key_id: None,
+            model_type: None,
+            bytes_processed: None,
+            description: String::new(),
+            created_at: NaiveDate::from_ymd_opt(2026, 10, 1)
+                .unwrap()
+                .and_hms_opt(0, minute, 0)
+                .unwrap(),
+        }
+    }
+
+    fn ledger() -> Vec<CreditTransaction> {
+        use CreditTransactionKind::*;
+        vec![
+            entry("1", PackPurchase, 500, Some(700)),
+            entry("2", MonthlyGrant, 800, Some(1500)),
+            entry("3", Usage, -300, Some(1200)),
+            entry("4", Refund, 50, Some(1250)),
+            entry("5", Usage, -100, None),
+            entry("6", ReferralReward, 250, Some(1400)),
+        ]
+    }
+
+    fn balance() -> CreditBalance {
+        CreditBalance {
+            used_current_period: 350,
+            max_monthly_credits: 1000,
+            remaining_credits: 650,
+            usage_percentage: 35.0,
+            reset_date: None,
+            extra_credits: 750,
+        }
+    }
+
+    #[test]
+    fn consistent_ledger_reconciles() {
+        // Listed out of order on purpose.
+        let mut transactions = ledger();
+        transactions.reverse();
+        assert_eq!(reconcile(&balance(), &transactions), vec![]);
+    }
+
+    #[test]
+    fn reports_each_kind_of_discrepancy() {
+        let mut transactions = ledger();
+        transactions[3].balance_after = Some(1260);
+        transactions[4].amount = -90;
+        let mut balance = balance();
+        balance.extra_credits = 700;
+
+        assert_eq!(
+            reconcile(&balance, &transactions),
+            vec![
+                LedgerDiscrepancy::RunningBalance {
+                    transaction_id: "4".to_string(),
+                    expected: 1250,
+                    recorded: 1260,
+                },
+                // Carried from 1260 across entry 5, which has no balance.
+                LedgerDiscrepancy::RunningBalance {
+                    transaction_id: "6".to_string(),
+                    expected: 1420,
+                    recorded: 1400,
+                },
+                LedgerDiscrepancy::PeriodUsage {
+                    ledger: 340,
+                    reported: 350,
+                },
+                LedgerDiscrepancy::Allowance {
+                    ledger: 660,
+                    reported: 650,
+                },
+                LedgerDiscrepancy::ExtraCredits {
+                    ledger: 740,
+                    reported: 700,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn a_gap_does_not_hide_the_next_bad_entry() {
+        let mut transactions = ledger();
+        transactions[5].balance_after = Some(1500);
+        assert_eq!(
+            reconcile(&balance(), &transactions)[0],
+            LedgerDiscrepancy::RunningBalance {
+                transaction_id: "6".to_string(),
+                expected: 1400,
+                recorded: 1500,
+            }
+        );
+    }
+
+    #[test]
+    fn extra_credits_are_checked_apart_from_the_allowance() {
+        // The total is right, but 50 credits sit in the wrong pool.
+        let balance = CreditBalance {
+            remaining_credits: 700,
+            extra_credits: 700,
+            ..balance()
+        };
+        assert_eq!(
+            reconcile(&balance, &ledger()),
+            vec![
+                LedgerDiscrepancy::Allowance {
+                    ledger: 650,
+                    reported: 700,
+                },
+                LedgerDiscrepancy::ExtraCredits {
+                    ledger: 750,
+                    reported: 700,
+                },
+            ]
+        );
+
+        // Without the grant, only the total can be checked.
+        let without_grant = ledger().split_off(2);
+        let balance = CreditBalance {
+            extra_credits: 800,
+            ..balance
+        };
+        assert_eq!(
+            reconcile(&balance, &without_grant),
+            vec![LedgerDiscrepancy::Balance {
+                ledger: 1400,
+                reported: 1500,
+            }]
+        );
+    }
+
+    fn bucket(id: &str, source: CreditSource, amount: i64, days: Option<i64>) -> CreditBucket {
+        CreditBucket {
+            id: id.to_string(),
+            source,
+            amount,
+            expires_at: days.map(|days| now() + chrono::Duration::days(days)),
+        }
+    }
+
+    fn now() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_760_000_000, 0).unwrap()
+    }
+
+    #[test]
+    fn spends_allowance_then_soonest_expiry() {
+        let buckets = [
+            bucket("pack", CreditSource::Purchase, 1000, None),
+            bucket("referral", CreditSource::ReferralReward, 200, Some(30)),
+            bucket("promo", CreditSource::Promotion, 100, Some(7)),
+            bucket("old", CreditSource::Promotion, 500, Some(-1)),
+        ];
+        assert_eq!(live_credits(&buckets, now()), 1300);
+
+        let consumption = consume(50, &buckets, 400, now());
+        assert_eq!(consumption.from_allowance, 50);
+        assert_eq!(
+            consumption.from_buckets,
+            vec![
+                BucketDraw {
+                    bucket_id: "promo".to_string(),
+                    amount: 100
+                },
+                BucketDraw {
+                    bucket_id: "referral".to_string(),
+                    amount: 200
+                },
+                BucketDraw {
+                    bucket_id: "pack".to_string(),
+                    amount: 50
+                },
+            ]
+        );
+        assert_eq!(consumption.shortfall, 0);
+
+        let consumption = consume(0, &buckets, 2000, now());
+        assert_eq!(consumption.shortfall, 700);
+    }
+
+    #[test]
+    fn allowance_alone_covers_small_charges() {
+        let buckets = [bucket("promo", CreditSource::Promotion, 100, Some(1))];
+        let consumption = consume(500, &buckets, 300, now());
+        assert_eq!(consumption.from_allowance, 300);
+        assert!(consumption.from_buckets.is_empty());
+    }
+
+    #[test]
+    fn usage_is_attributed_to_keys() {
+        use CreditTransactionKind::*;
+        let by_key = |id: &str, mut transaction: CreditTransaction| {
+            transaction.api_key_id = Some(id.to_string());
+            transaction
+        };
+        let transactions = vec![
+            by_key("ci", entry("1", Usage, -300, None)),
+            by_key("bot", entry("2", Usage, -100, None)),
+            by_key("ci", entry("3", Refund, 50, None)),
+            entry("4", Usage, -1000, None),
+            entry("5", PackPurchase, 500, None),
+        ];
+        assert_eq!(
+            usage_by_api_key(&transactions),
+            HashMap::from([("ci".to_string(), 250), ("bot".to_string(), 100)])
+        );
+    }
+
+    #[test]
+    fn unknown_kinds_survive_a_round_trip() {
+        let kind: CreditTransactionKind = serde_json::from_str("\"promo\"").unwrap();
+        assert_eq!(kind, CreditTransactionKind::Unknown("promo".to_string()));
+        assert_eq!(serde_json::to_string(&kind).unwrap(), "\"promo\"");
+        assert_eq!(
+            serde_json::to_string(&CreditTransactionKind::MonthlyGrant).unwrap(),
+            "\"monthly_grant\""
+        );
+    }
+}
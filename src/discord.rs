@@ -1,4 +1,5 @@
-use crate::moderate::{ModerationLabel, ModerationModel};
+use crate::error::ParseEnumError;
+use crate::moderate::{ModerationLabel, ModerationModel, ModerationResponse};
 use crate::pricing::Tier;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
@@ -178,7 +179,17 @@ pub struct GuildConfig {
     #[serde(default = "default_role_filter_mode")]
     pub role_filter_mode: RoleFilterMode,
     pub filtered_roles: HashMap<String, RoleInfo>,
+    /// Fallback actions for any label without an entry in `label_actions`.
     pub actions: HashSet<ModerationAction>,
+    /// Per-label overrides of `actions`, so spam can be deleted quietly while
+    /// hate speech earns a day's timeout. Use [`GuildConfig::resolve_actions`]
+    /// rather than reading either field directly.
+    #[serde(default)]
+    pub label_actions: HashMap<ModerationLabel, LabelActionPolicy>,
+    /// Role assigned by `ModerationAction::QuarantineRole`. Without one that
+    /// action is dropped from the plan.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quarantine_role_id: Option<String>,
     #[serde(default = "default_is_active")]
     pub is_active: bool,
     #[serde(default = "default_model")]
@@ -235,6 +246,12 @@ pub struct GuildConfig {
     pub verify_channel_id: Option<String>,
     #[serde(default)]
     pub enable_username_check: bool,
+    #[serde(default)]
+    pub strike_policy: crate::infractions::StrikePolicy,
+    /// Organization whose credits pay for this guild's moderation, instead of
+    /// the member who added the bot.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub billing_org_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -349,13 +366,17 @@ pub enum LinkFilterMode {
 }
 
 impl FromStr for LinkFilterMode {
-    type Err = ();
+    type Err = ParseEnumError;
 
     fn from_str(input: &str) -> Result<LinkFilterMode, Self::Err> {
         match input {
             "whitelist" => Ok(LinkFilterMode::Whitelist),
             "blacklist" => Ok(LinkFilterMode::Blacklist),
-            _ => Err(()),
+            _ => Err(ParseEnumError::new(
+                input,
+                "LinkFilterMode",
+                &["whitelist", "blacklist"],
+            )),
         }
     }
 }
@@ -366,6 +387,65 @@ pub enum ModerationAction {
     Delete,
     Timeout,
     Warn,
+    Kick,
+    Ban,
+    #[serde(rename = "quarantine_role")]
+    QuarantineRole,
+}
+
+impl ModerationAction {
+    /// Position in an action plan. Cheap, reversible actions run first, and a
+    /// warning is sent before the member can be removed and stop receiving it.
+    fn plan_order(&self) -> u8 {
+        match self {
+            ModerationAction::Delete => 0,
+            ModerationAction::Warn => 1,
+            ModerationAction::QuarantineRole => 2,
+            ModerationAction::Timeout => 3,
+            ModerationAction::Kick => 4,
+            ModerationAction::Ban => 5,
+        }
+    }
+
+    /// Whether running `self` makes `other` pointless: a member who is banned
+    /// or kicked cannot also be timed out or quarantined.
+    fn supersedes(&self, other: &ModerationAction) -> bool {
+        match self {
+            ModerationAction::Ban => matches!(
+                other,
+                ModerationAction::Kick
+                    | ModerationAction::Timeout
+                    | ModerationAction::QuarantineRole
+            ),
+            ModerationAction::Kick => {
+                matches!(
+                    other,
+                    ModerationAction::Timeout | ModerationAction::QuarantineRole
+                )
+            }
+            _ => false,
+        }
+    }
+}
+
+/// What to do about one label. `timeout_duration_minutes` overrides the
+/// guild-wide duration when `actions` includes a timeout.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LabelActionPolicy {
+    pub actions: HashSet<ModerationAction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_duration_minutes: Option<i32>,
+}
+
+/// One step of the plan [`GuildConfig::resolve_actions`] returns.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PlannedAction {
+    pub action: ModerationAction,
+    /// Set for `Timeout` only: the longest duration any triggering label asks for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_duration_minutes: Option<i32>,
+    /// Labels that asked for this action, in [`ModerationLabel::all_labels`] order.
+    pub labels: Vec<ModerationLabel>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
@@ -375,6 +455,63 @@ pub enum RoleFilterMode {
     Exclude,
 }
 
+impl GuildConfig {
+    /// The actions to take for a moderation result, in the order to run them.
+    ///
+    /// Only labels the guild has enabled count. Each uses its entry in
+    /// `label_actions`, or `actions` and `timeout_duration_minutes` when it has
+    /// none, which is how every config written before per-label policies
+    /// behaves. Actions asked for by several labels appear once, and actions a
+    /// harsher one makes pointless are dropped.
+    pub fn resolve_actions(&self, response: &ModerationResponse) -> Vec<PlannedAction> {
+        let mut plan: Vec<PlannedAction> = Vec::new();
+        for label in ModerationLabel::all_labels() {
+            if !response.labels.contains(&label) || !self.enabled_labels.contains(&label) {
+                continue;
+            }
+            let (actions, timeout) = match self.label_actions.get(&label) {
+                Some(policy) => (
+                    &policy.actions,
+                    policy
+                        .timeout_duration_minutes
+                        .unwrap_or(self.timeout_duration_minutes),
+                ),
+                None => (&self.actions, self.timeout_duration_minutes),
+            };
+            for action in actions {
+                if *action == ModerationAction::QuarantineRole && self.quarantine_role_id.is_none()
+                {
+                    continue;
+                }
+                let timeout = (*action == ModerationAction::Timeout).then_some(timeout);
+                match plan.iter_mut().find(|step| step.action == *action) {
+                    Some(step) => {
+                        step.labels.push(label.clone());
+                        step.timeout_duration_minutes = step.timeout_duration_minutes.max(timeout);
+                    }
+                    None => plan.push(PlannedAction {
+                        action: action.clone(),
+                        timeout_duration_minutes: timeout,
+                        labels: vec![label.clone()],
+                    }),
+                }
+            }
+        }
+
+        let actions: Vec<ModerationAction> = plan.iter().map(|step| step.action.clone()).collect();
+        plan.retain(|step| !actions.iter().any(|other| other.supersedes(&step.action)));
+        plan.sort_by_key(|step| step.action.plan_order());
+        plan
+    }
+
+    /// Enabled labels grouped the way the dashboard lays out its toggles.
+    pub fn enabled_labels_by_category(
+        &self,
+    ) -> Vec<(crate::moderate::LabelCategory, Vec<ModerationLabel>)> {
+        crate::moderate::LabelCategory::group(&self.enabled_labels)
+    }
+}
+
 impl Default for GuildConfig {
     fn default() -> Self {
         Self {
@@ -398,6 +535,8 @@ impl Default for GuildConfig {
             role_filter_mode: default_role_filter_mode(),
             filtered_roles: HashMap::new(),
             actions: HashSet::from([ModerationAction::Delete]),
+            label_actions: HashMap::new(),
+            quarantine_role_id: None,
             is_active: default_is_active(),
             model: default_model(),
             alerts_channel: None,
@@ -419,10 +558,13 @@ impl Default for GuildConfig {
             verified_role_id: None,
             verify_channel_id: None,
             enable_username_check: false,
+            strike_policy: Default::default(),
+            billing_org_id: None,
         }
     }
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for GuildContext {
     fn default() -> Self {
         GuildContext {
@@ -433,26 +575,44 @@ impl Default for GuildContext {
 }
 
 impl FromStr for ModerationAction {
-    type Err = ();
+    type Err = ParseEnumError;
 
     fn from_str(input: &str) -> Result<ModerationAction, Self::Err> {
         match input {
             "delete" => Ok(ModerationAction::Delete),
             "timeout" => Ok(ModerationAction::Timeout),
             "warn" => Ok(ModerationAction::Warn),
-            _ => Err(()),
+            "kick" => Ok(ModerationAction::Kick),
+            "ban" => Ok(ModerationAction::Ban),
+            "quarantine_role" => Ok(ModerationAction::QuarantineRole),
+            _ => Err(ParseEnumError::new(
+                input,
+                "ModerationAction",
+                &[
+                    "delete",
+                    "timeout",
+                    "warn",
+                    "kick",
+                    "ban",
+                    "quarantine_role",
+                ],
+            )),
         }
     }
 }
 
 impl FromStr for RoleFilterMode {
-    type Err = ();
+    type Err = ParseEnumError;
 
     fn from_str(input: &str) -> Result<RoleFilterMode, Self::Err> {
         match input {
             "include" => Ok(RoleFilterMode::Include),
             "exclude" => Ok(RoleFilterMode::Exclude),
-            _ => Err(()),
+            _ => Err(ParseEnumError::new(
+                input,
+                "RoleFilterMode",
+                &["include", "exclude"],
+            )),
         }
     }
 }
@@ -463,6 +623,9 @@ impl std::fmt::Display for ModerationAction {
             ModerationAction::Delete => write!(f, "delete"),
             ModerationAction::Timeout => write!(f, "timeout"),
             ModerationAction::Warn => write!(f, "warn"),
+            ModerationAction::Kick => write!(f, "kick"),
+            ModerationAction::Ban => write!(f, "ban"),
+            ModerationAction::QuarantineRole => write!(f, "quarantine_role"),
         }
     }
 }
@@ -492,3 +655,107 @@ pub struct AccountLinkedNotification {
     pub discord_id: String,
     pub is_new_account: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flagged(labels: &[ModerationLabel]) -> ModerationResponse {
+        ModerationResponse {
+            flagged: true,
+            labels: labels.to_vec(),
+            implicit_labels: None,
+            model_version: None,
+            needs_context: None,
+            context_labels: None,
+            rewritten_text: None,
+            extracted_text: None,
+            scores: None,
+        }
+    }
+
+    #[test]
+    fn configs_without_label_actions_still_deserialize() {
+        let config: GuildConfig = serde_json::from_value(serde_json::json!({
+            "moderated_channels": {},
+            "enabled_labels": ["HR", "spam"],
+            "filtered_roles": {},
+            "actions": ["delete", "timeout"],
+            "timeout_duration_minutes": 10
+        }))
+        .unwrap();
+        assert!(config.label_actions.is_empty());
+
+        let plan = config.resolve_actions(&flagged(&[ModerationLabel::HR]));
+        assert_eq!(
+            plan,
+            vec![
+                PlannedAction {
+                    action: ModerationAction::Delete,
+                    timeout_duration_minutes: None,
+                    labels: vec![ModerationLabel::HR],
+                },
+                PlannedAction {
+                    action: ModerationAction::Timeout,
+                    timeout_duration_minutes: Some(10),
+                    labels: vec![ModerationLabel::HR],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn per_label_policies_merge_and_escalate() {
+        let mut config = GuildConfig::default();
+        config.label_actions.insert(
+            ModerationLabel::HR,
+            LabelActionPolicy {
+                actions: HashSet::from([ModerationAction::Delete, ModerationAction::Timeout]),
+                timeout_duration_minutes: Some(1440),
+            },
+        );
+        config.label_actions.insert(
+            ModerationLabel::SU,
+            LabelActionPolicy {
+                actions: HashSet::from([ModerationAction::Ban]),
+                timeout_duration_minutes: None,
+            },
+        );
+
+        let plan = config.resolve_actions(&flagged(&[ModerationLabel::SP, ModerationLabel::HR]));
+        let actions: Vec<_> = plan.iter().map(|step| step.action.clone()).collect();
+        assert_eq!(
+            actions,
+            vec![ModerationAction::Delete, ModerationAction::Timeout]
+        );
+        assert_eq!(
+            plan[0].labels,
+            vec![ModerationLabel::HR, ModerationLabel::SP]
+        );
+        assert_eq!(plan[1].timeout_duration_minutes, Some(1440));
+
+        // The ban makes HR's timeout pointless.
+        let plan = config.resolve_actions(&flagged(&[ModerationLabel::HR, ModerationLabel::SU]));
+        let actions: Vec<_> = plan.iter().map(|step| step.action.clone()).collect();
+        assert_eq!(
+            actions,
+            vec![ModerationAction::Delete, ModerationAction::Ban]
+        );
+    }
+
+    #[test]
+    fn disabled_labels_and_unconfigured_quarantine_are_ignored() {
+        let mut config = GuildConfig {
+            actions: HashSet::from([ModerationAction::QuarantineRole]),
+            ..Default::default()
+        };
+        let spam = flagged(&[ModerationLabel::SP]);
+        assert!(config.resolve_actions(&spam).is_empty());
+
+        config.quarantine_role_id = Some("42".to_string());
+        assert_eq!(config.resolve_actions(&spam).len(), 1);
+        // T is not enabled by default.
+        let toxic = flagged(&[ModerationLabel::T]);
+        assert!(config.resolve_actions(&toxic).is_empty());
+    }
+}
@@ -19,3 +19,36 @@ impl std::fmt::Display for ApiError {
 }
 
 impl std::error::Error for ApiError {}
+
+/// A string that names none of an enum's variants. Carries enough to tell a
+/// user what they typed and what would have worked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseEnumError {
+    pub input: String,
+    pub type_name: &'static str,
+    pub expected: &'static [&'static str],
+}
+
+impl ParseEnumError {
+    pub fn new(input: &str, type_name: &'static str, expected: &'static [&'static str]) -> Self {
+        Self {
+            input: input.to_string(),
+            type_name,
+            expected,
+        }
+    }
+}
+
+impl std::fmt::Display for ParseEnumError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "invalid {} \"{}\", expected one of: {}",
+            self.type_name,
+            self.input,
+            self.expected.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for ParseEnumError {}
@@ -0,0 +1,587 @@
+//! "Will I run out before the reset?", answered from the daily usage series.
+//!
+//! Usage is projected one weekday at a time, since Discord traffic peaks at
+//! weekends and a flat average would call a Friday exhaustion for a Monday.
+//! The band around the expected date comes from how much each weekday varies
+//! from week to week: the earliest date assumes every day runs one standard
+//! deviation busy, the latest one standard deviation quiet.
+
+use crate::credits::CreditProductResponse;
+use crate::money::Money;
+use crate::pricing::{
+    BillingCycle, CreditsInfoResponse, DailyUsageEntry, PricingData, StripeSubscriptionInfo, Tier,
+};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// How far ahead to look when there is no reset date to stop at.
+pub const FORECAST_HORIZON_DAYS: i64 = 90;
+
+/// Most days of history the forecast reads, counting back from the latest
+/// entry. Half a year is plenty to see a weekly pattern, and anything older
+/// describes a server that has likely changed since.
+pub const MAX_HISTORY_DAYS: i64 = 182;
+
+/// Samples each weekday needs before it gets its own average. With fewer, one
+/// odd day would pass for a weekly pattern, so every day gets the overall
+/// average instead.
+const MIN_SAMPLES_PER_WEEKDAY: usize = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ForecastError {
+    /// No usage recorded, so there is nothing to project from.
+    NoHistory,
+    /// A `day` that is not `YYYY-MM-DD`.
+    InvalidDay { day: String },
+}
+
+impl std::fmt::Display for ForecastError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ForecastError::NoHistory => write!(f, "no usage history to forecast from"),
+            ForecastError::InvalidDay { day } => write!(f, "invalid usage day \"{}\"", day),
+        }
+    }
+}
+
+impl std::error::Error for ForecastError {}
+
+/// When the balance runs out under each scenario. `None` means it lasts past
+/// the reset date, or past [`FORECAST_HORIZON_DAYS`] when there is none.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExhaustionBand {
+    pub earliest: Option<NaiveDate>,
+    pub expected: Option<NaiveDate>,
+    pub latest: Option<NaiveDate>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UsageForecast {
+    /// Expected credits per day, Monday first.
+    pub weekday_averages: [f64; 7],
+    /// Whether `weekday_averages` reflects a weekly pattern, or is the same
+    /// overall average repeated because the history is too short.
+    pub seasonal: bool,
+    pub exhaustion: ExhaustionBand,
+    /// Credits missing to reach the reset date at the expected rate, or zero
+    /// if the balance lasts. Always zero without a reset date.
+    pub expected_shortfall: i64,
+}
+
+/// A way to cover a shortfall.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TopUp {
+    /// Run into the overdraft, at the rate given to [`top_up_options`].
+    Overdraft,
+    /// Buy `quantity` of one credit pack.
+    CreditPack { price_id: String, quantity: i64 },
+    /// Move to a plan whose monthly allowance covers the shortfall.
+    PlanChange {
+        tier: Tier,
+        billing_cycle: BillingCycle,
+    },
+    /// Keep the plan but move it to another billing cycle. The new cycle
+    /// starts a new period now, and with it a full monthly allowance.
+    ChangeCycle { billing_cycle: BillingCycle },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TopUpOption {
+    #[serde(flatten)]
+    pub top_up: TopUp,
+    /// One-off cost, or for a plan change the extra cost per month. The
+    /// forecast assumes usage stays as it is, so a shortfall this month is
+    /// one every month and the two compare fairly. A cycle change costs what
+    /// [`StripeSubscriptionInfo::preview_change`] says is charged on
+    /// confirming.
+    pub cost: Money,
+}
+
+struct DayStats {
+    mean: f64,
+    std_dev: f64,
+}
+
+fn stats(samples: &[f64]) -> DayStats {
+    let n = samples.len().max(1) as f64;
+    let mean = samples.iter().sum::<f64>() / n;
+    let variance = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n;
+    DayStats {
+        mean,
+        std_dev: variance.sqrt(),
+    }
+}
+
+/// Project `info.balance` forward from `today` using `history`.
+///
+/// Days missing between the first and last entry count as zero usage, since
+/// the series skips days with nothing used. Only the last
+/// [`MAX_HISTORY_DAYS`] up to the latest entry are read.
+pub fn forecast(
+    history: &[DailyUsageEntry],
+    info: &CreditsInfoResponse,
+    today: NaiveDate,
+) -> Result<UsageForecast, ForecastError> {
+    let mut used_on: HashMap<NaiveDate, i64> = HashMap::new();
+    for entry in history {
+        let day = NaiveDate::parse_from_str(&entry.day, "%Y-%m-%d").map_err(|_| {
+            ForecastError::InvalidDay {
+                day: entry.day.clone(),
+            }
+        })?;
+        *used_on.entry(day).or_insert(0) += entry.credits_used;
+    }
+    let (first, last) = match (used_on.keys().min(), used_on.keys().max()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => return Err(ForecastError::NoHistory),
+    };
+    let first = first.max(last - Duration::days(MAX_HISTORY_DAYS - 1));
+
+    let mut by_weekday: [Vec<f64>; 7] = Default::default();
+    let mut all = Vec::new();
+    for day in first.iter_days().take_while(|day| *day <= last) {
+        let used = used_on.get(&day).copied().unwrap_or(0);
+        by_weekday[day.weekday().num_days_from_monday() as usize].push(used as f64);
+        all.push(used as f64);
+    }
+
+    let seasonal = by_weekday
+        .iter()
+        .all(|samples| samples.len() >= MIN_SAMPLES_PER_WEEKDAY);
+    let profile: Vec<DayStats> = if seasonal {
+        by_weekday.iter().map(|samples| stats(samples)).collect()
+    } else {
+        (0..7).map(|_| stats(&all)).collect()
+    };
+
+    let reset = info.reset_date.map(|reset| reset.date());
+    let horizon = reset.unwrap_or(today + Duration::days(FORECAST_HORIZON_DAYS));
+    let rate = |day: NaiveDate, spread: f64| {
+        let day = &profile[day.weekday().num_days_from_monday() as usize];
+        (day.mean + spread * day.std_dev).max(0.0)
+    };
+    let exhausted_on = |spread: f64| {
+        let mut remaining = info.balance as f64;
+        today
+            .iter_days()
+            .take_while(|day| *day < horizon)
+            .find(|day| {
+                remaining -= rate(*day, spread);
+                remaining < 0.0
+            })
+    };
+
+    let expected_shortfall = match reset {
+        Some(reset) => {
+            let needed: f64 = today
+                .iter_days()
+                .take_while(|day| *day < reset)
+                .map(|day| rate(day, 0.0))
+                .sum();
+            (needed - info.balance as f64).max(0.0).ceil() as i64
+        }
+        None => 0,
+    };
+
+    Ok(UsageForecast {
+        weekday_averages: std::array::from_fn(|i| profile[i].mean),
+        seasonal,
+        exhaustion: ExhaustionBand {
+            earliest: exhausted_on(1.0),
+            expected: exhausted_on(0.0),
+            latest: exhausted_on(-1.0),
+        },
+        expected_shortfall,
+    })
+}
+
+/// Ways to cover `shortfall` credits, cheapest first. Empty when there is no
+/// shortfall.
+///
+/// Overdraft is only offered within the headroom left under the customer's
+/// limit, whether or not it is switched on yet. A plan change is offered for
+/// any plan whose allowance covers the current one plus the shortfall, costed
+/// per month against what the customer pays now. A cycle change is offered
+/// for each other cycle of the current plan that is not a downgrade, when a
+/// fresh allowance covers the shortfall. Options priced in a currency other
+/// than the overdraft's are left out, since they cannot be compared.
+///
+/// `overdraft_cost` prices credits drawn from the overdraft. Pass
+/// [`crate::pricing::credits_to_gbp`] for the standard rate, which is what
+/// [`crate::budget`] assumes when it caps overdraft spend.
+pub fn top_up_options(
+    shortfall: i64,
+    subscription: &StripeSubscriptionInfo,
+    pricing: &PricingData,
+    packs: &[CreditProductResponse],
+    overdraft_cost: impl Fn(i64) -> Money,
+    now: DateTime<Utc>,
+) -> Vec<TopUpOption> {
+    if shortfall <= 0 {
+        return Vec::new();
+    }
+    let overdraft_cost = overdraft_cost(shortfall);
+    let currency = overdraft_cost.currency.clone();
+    let mut options = Vec::new();
+
+    let headroom =
+        subscription.overdraft_limit.unwrap_or(0) - subscription.overdraft_used.unwrap_or(0);
+    if headroom >= shortfall {
+        options.push(TopUpOption {
+            top_up: TopUp::Overdraft,
+            cost: overdraft_cost,
+        });
+    }
+
+    for pack in packs
+        .iter()
+        .filter(|pack| pack.credits_amount > 0 && pack.price.currency == currency)
+    {
+        let quantity = (shortfall + pack.credits_amount - 1) / pack.credits_amount;
+        options.push(TopUpOption {
+            top_up: TopUp::CreditPack {
+                price_id: pack.price_id.clone(),
+                quantity,
+            },
+            cost: pack.price.mul_ratio(quantity, 1),
+        });
+    }
+
+    let monthly = |price: &Money, cycle: BillingCycle| price.mul_ratio(1, cycle.months() as i64);
+    let current_plan = pricing
+        .tiers
+        .iter()
+        .find(|plan| plan.tier == subscription.tier);
+    let current_allowance = current_plan
+        .and_then(|plan| plan.monthly_credits)
+        .or(pricing.free_tier_credits)
+        .unwrap_or(0);
+
+    if let Some(plan) = current_plan.filter(|_| subscription.is_active)
+        && current_allowance >= shortfall
+    {
+        for cycle in plan.prices.keys() {
+            let Ok(preview) = subscription.preview_change(plan, plan, *cycle, now) else {
+                continue;
+            };
+            if !preview.is_downgrade && preview.immediate_charge.currency == currency {
+                options.push(TopUpOption {
+                    top_up: TopUp::ChangeCycle {
+                        billing_cycle: *cycle,
+                    },
+                    cost: preview.immediate_charge,
+                });
+            }
+        }
+    }
+
+    let current_monthly = subscription
+        .price
+        .money()
+        .filter(|_| subscription.is_active)
+        .map(|price| monthly(price, subscription.billing_cycle))
+        .unwrap_or_else(|| Money::zero(currency.clone()));
+    for plan in &pricing.tiers {
+        if plan.monthly_credits.unwrap_or(0) < current_allowance + shortfall {
+            continue;
+        }
+        for (cycle, price) in &plan.prices {
+            if let Some(cost) = monthly(&price.amount, *cycle).checked_sub(&current_monthly) {
+                options.push(TopUpOption {
+                    top_up: TopUp::PlanChange {
+                        tier: plan.tier,
+                        billing_cycle: *cycle,
+                    },
+                    cost,
+                });
+            }
+        }
+    }
+
+    options.sort_by_key(|option| option.cost.minor);
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Currency;
+    use crate::pricing::{FormattedPrice, TierPricing, credits_to_gbp};
+
+    fn now() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_760_000_000, 0).unwrap()
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    fn info(balance: i64, reset: &str) -> CreditsInfoResponse {
+        CreditsInfoResponse {
+            balance,
+            monthly_allocation: 0,
+            used_this_month: 0,
+            remaining_this_month: balance,
+            usage_percentage: 0.0,
+            reset_date: Some(date(reset).and_hms_opt(0, 0, 0).unwrap()),
+            extra_credits: 0,
+            discount_available: false,
+            discount_expires_at: None,
+            discount_percentage: None,
+            rate_limited: false,
+            rate_limit_resets_at: None,
+            trial_available: false,
+            buckets: Vec::new(),
+        }
+    }
+
+    /// Four weeks from Monday 2026-09-07: 100 a weekday, 400 a weekend day,
+    /// the weekends alternating 300 and 500.
+    fn weekend_heavy() -> Vec<DailyUsageEntry> {
+        date("2026-09-07")
+            .iter_days()
+            .take(28)
+            .map(|day| {
+                let credits_used = match day.weekday().num_days_from_monday() {
+                    5 | 6 if day.iso_week().week() % 2 == 0 => 300,
+                    5 | 6 => 500,
+                    _ => 100,
+                };
+                DailyUsageEntry {
+                    day: day.format("%Y-%m-%d").to_string(),
+                    credits_used,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn weekends_bring_exhaustion_forward() {
+        // From Monday 2026-10-05 the weekdays use 500, leaving 300 for a
+        // Saturday expected to use 400.
+        let forecast = forecast(
+            &weekend_heavy(),
+            &info(800, "2026-10-31"),
+            date("2026-10-05"),
+        )
+        .unwrap();
+        assert!(forecast.seasonal);
+        assert_eq!(forecast.weekday_averages[0], 100.0);
+        assert_eq!(forecast.weekday_averages[5], 400.0);
+        assert_eq!(forecast.exhaustion.expected, Some(date("2026-10-10")));
+        // A quiet Saturday (300) uses exactly what is left, so the balance
+        // only goes negative on Sunday.
+        assert_eq!(forecast.exhaustion.earliest, Some(date("2026-10-10")));
+        assert_eq!(forecast.exhaustion.latest, Some(date("2026-10-11")));
+        assert!(forecast.expected_shortfall > 0);
+    }
+
+    #[test]
+    fn short_histories_fall_back_to_a_flat_average() {
+        let history = &weekend_heavy()[..7];
+        let forecast =
+            forecast(history, &info(1_000_000, "2026-10-31"), date("2026-10-05")).unwrap();
+        assert!(!forecast.seasonal);
+        assert_eq!(forecast.weekday_averages, [1500.0 / 7.0; 7]);
+        assert_eq!(forecast.exhaustion.expected, None);
+        assert_eq!(forecast.expected_shortfall, 0);
+    }
+
+    #[test]
+    fn only_the_latest_history_is_read() {
+        // The history ends on 2026-10-04, so the window opens on 2026-04-06.
+        // A busy day just before it counts no more than a quiet day inside.
+        let with_day = |day: &str, credits_used: i64| {
+            let mut history = weekend_heavy();
+            history.push(DailyUsageEntry {
+                day: day.to_string(),
+                credits_used,
+            });
+            forecast(&history, &info(1_000_000, "2026-10-31"), date("2026-10-05")).unwrap()
+        };
+        assert_eq!(with_day("2026-04-05", 1_000_000), with_day("2026-04-06", 0));
+        assert_ne!(with_day("2026-04-06", 1_000_000), with_day("2026-04-06", 0));
+    }
+
+    #[test]
+    fn rejects_bad_history() {
+        assert_eq!(
+            forecast(&[], &info(0, "2026-10-31"), date("2026-10-05")),
+            Err(ForecastError::NoHistory)
+        );
+        let bad = [DailyUsageEntry {
+            day: "05/10/2026".to_string(),
+            credits_used: 1,
+        }];
+        assert_eq!(
+            forecast(&bad, &info(0, "2026-10-31"), date("2026-10-05")),
+            Err(ForecastError::InvalidDay {
+                day: "05/10/2026".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn cheapest_top_up_comes_first() {
+        let subscription = StripeSubscriptionInfo {
+            overdraft_limit: Some(5_000_000),
+            ..StripeSubscriptionInfo::free()
+        };
+        let pricing = PricingData {
+            tiers: vec![TierPricing {
+                tier: Tier::Premium,
+                prices: [(
+                    BillingCycle::Monthly,
+                    FormattedPrice {
+                        price_id: "premium".to_string(),
+                        amount: Money::gbp(1500),
+                    },
+                )]
+                .into_iter()
+                .collect(),
+                monthly_credits: Some(10_000_000),
+                feature_names: Vec::new(),
+            }],
+            billing_cycles: Vec::new(),
+            flash_sale: None,
+            free_tier_credits: Some(100_000),
+        };
+        let pack = |price_id: &str, pence: i64, credits_amount: i64| CreditProductResponse {
+            id: price_id.to_string(),
+            price_id: price_id.to_string(),
+            name: price_id.to_string(),
+            description: None,
+            price: Money::gbp(pence),
+            credits_amount,
+        };
+        let mut packs = [
+            pack("small", 500, 3_000_000),
+            pack("dollars", 100, 3_000_000),
+        ];
+        packs[1].price.currency = Currency::Usd;
+
+        // 4.5M credits: £6 of overdraft, two small packs for £10, or £15 a
+        // month more for Premium. The dollar pack cannot be compared.
+        let options = top_up_options(
+            4_500_000,
+            &subscription,
+            &pricing,
+            &packs,
+            credits_to_gbp,
+            now(),
+        );
+        let costs: Vec<(&TopUp, i64)> = options.iter().map(|o| (&o.top_up, o.cost.minor)).collect();
+        assert_eq!(
+            costs,
+            vec![
+                (&TopUp::Overdraft, 600),
+                (
+                    &TopUp::CreditPack {
+                        price_id: "small".to_string(),
+                        quantity: 2
+                    },
+                    1000
+                ),
+                (
+                    &TopUp::PlanChange {
+                        tier: Tier::Premium,
+                        billing_cycle: BillingCycle::Monthly
+                    },
+                    1500
+                ),
+            ]
+        );
+
+        // Past the overdraft headroom, it is no longer offered.
+        let options = top_up_options(
+            6_000_000,
+            &subscription,
+            &pricing,
+            &packs,
+            credits_to_gbp,
+            now(),
+        );
+        assert!(options.iter().all(|o| o.top_up != TopUp::Overdraft));
+        assert!(
+            top_up_options(0, &subscription, &pricing, &packs, credits_to_gbp, now()).is_empty()
+        );
+    }
+
+    #[test]
+    fn a_cycle_change_refills_the_allowance() {
+        let subscription = StripeSubscriptionInfo {
+            tier: Tier::Premium,
+            status: crate::pricing::SubscriptionStatus::Active,
+            is_active: true,
+            current_period_end: Some(now() + Duration::days(15)),
+            price: Money::gbp(1500).into(),
+            ..StripeSubscriptionInfo::free()
+        };
+        let price = |pence: i64| FormattedPrice {
+            price_id: format!("premium-{}", pence),
+            amount: Money::gbp(pence),
+        };
+        let pricing = PricingData {
+            tiers: vec![TierPricing {
+                tier: Tier::Premium,
+                prices: [
+                    (BillingCycle::Monthly, price(1500)),
+                    (BillingCycle::Annual, price(15000)),
+                ]
+                .into_iter()
+                .collect(),
+                monthly_credits: Some(10_000_000),
+                feature_names: Vec::new(),
+            }],
+            billing_cycles: Vec::new(),
+            flash_sale: None,
+            free_tier_credits: Some(100_000),
+        };
+
+        // A year at £150, less the unused half of this month's £15. Staying
+        // on monthly is the current plan, so it is not an option.
+        let options = top_up_options(
+            4_500_000,
+            &subscription,
+            &pricing,
+            &[],
+            credits_to_gbp,
+            now(),
+        );
+        assert_eq!(
+            options,
+            vec![TopUpOption {
+                top_up: TopUp::ChangeCycle {
+                    billing_cycle: BillingCycle::Annual
+                },
+                cost: Money::gbp(14250),
+            }]
+        );
+
+        // A shorter cycle is a downgrade, which waits for the period to end.
+        let annual = StripeSubscriptionInfo {
+            billing_cycle: BillingCycle::Annual,
+            current_period_end: Some(now() + Duration::days(180)),
+            price: Money::gbp(15000).into(),
+            ..subscription.clone()
+        };
+        assert!(
+            top_up_options(4_500_000, &annual, &pricing, &[], credits_to_gbp, now()).is_empty()
+        );
+
+        // A fresh allowance smaller than the shortfall does not cover it.
+        assert!(
+            top_up_options(
+                20_000_000,
+                &subscription,
+                &pricing,
+                &[],
+                credits_to_gbp,
+                now()
+            )
+            .is_empty()
+        );
+    }
+}
@@ -0,0 +1,308 @@
+use crate::discord::ModerationAction;
+use crate::moderate::ModerationLabel;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// One label a member was moderated for. A message flagged for several labels
+/// is recorded once per label, but only ever counts as one strike.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Infraction {
+    pub guild_id: String,
+    pub user_id: String,
+    pub message_id: String,
+    pub label: ModerationLabel,
+    /// The action actually taken, after any escalation.
+    pub action: ModerationAction,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemberInfractionsResponse {
+    pub guild_id: String,
+    pub user_id: String,
+    pub infractions: Vec<Infraction>,
+    /// Strikes that have not decayed yet, as [`StrikePolicy::active_strikes`]
+    /// counts them.
+    pub active_strikes: u32,
+}
+
+/// Escalation for repeat offenders.
+///
+/// A rung is reached by that many strikes within the last `window_hours`.
+/// Separately, a strike decays `decay_hours` after the message that earned it:
+/// it drops out of the member's active count, and no longer counts towards a
+/// rung even if the window is longer.
+///
+/// Lives here rather than in the bot so the dashboard's member history shows
+/// the same strike count the bot acted on.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StrikePolicy {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Hours back from a violation in which strikes count towards a rung.
+    /// Negative counts as zero, and too large to represent means all of them.
+    #[serde(default = "default_window_hours")]
+    pub window_hours: i64,
+    /// Hours a strike stays in force. Negative counts as zero, and too large
+    /// to represent means strikes never decay.
+    #[serde(default = "default_decay_hours")]
+    pub decay_hours: i64,
+    /// Rungs of the ladder. Order does not matter; the highest rung reached
+    /// wins.
+    #[serde(default = "default_escalations")]
+    pub escalations: Vec<StrikeEscalation>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StrikeEscalation {
+    pub strikes: u32,
+    pub action: ModerationAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_duration_minutes: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StrikeOutcome {
+    /// Strikes in force, including the new violation.
+    pub active_strikes: u32,
+    /// Of those, the ones inside the escalation window: what the rungs are
+    /// measured against.
+    pub window_strikes: u32,
+    /// The rung reached, if any. `None` means the guild's normal actions apply.
+    pub escalation: Option<StrikeEscalation>,
+}
+
+fn default_window_hours() -> i64 {
+    24
+}
+
+fn default_decay_hours() -> i64 {
+    24 * 7
+}
+
+fn default_escalations() -> Vec<StrikeEscalation> {
+    vec![
+        StrikeEscalation {
+            strikes: 3,
+            action: ModerationAction::Timeout,
+            timeout_duration_minutes: Some(60),
+        },
+        StrikeEscalation {
+            strikes: 5,
+            action: ModerationAction::Kick,
+            timeout_duration_minutes: None,
+        },
+    ]
+}
+
+impl Default for StrikePolicy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            window_hours: default_window_hours(),
+            decay_hours: default_decay_hours(),
+            escalations: default_escalations(),
+        }
+    }
+}
+
+impl StrikePolicy {
+    /// Distinct messages by the member in `history` that are still in force
+    /// at `now`. Records for other members or guilds are ignored, so a whole
+    /// guild's history can be passed in.
+    pub fn active_strikes(
+        &self,
+        history: &[Infraction],
+        guild_id: &str,
+        user_id: &str,
+        now: DateTime<Utc>,
+    ) -> u32 {
+        strikes_since(history, guild_id, user_id, now, self.decay_hours)
+    }
+
+    /// Like [`StrikePolicy::active_strikes`], but only those inside the
+    /// escalation window.
+    pub fn window_strikes(
+        &self,
+        history: &[Infraction],
+        guild_id: &str,
+        user_id: &str,
+        now: DateTime<Utc>,
+    ) -> u32 {
+        let hours = self.window_hours.min(self.decay_hours);
+        strikes_since(history, guild_id, user_id, now, hours)
+    }
+
+    /// Where `violation` puts the member on the ladder, given what they had
+    /// already done. Nothing escalates while the policy is disabled, though the
+    /// strike count is still reported.
+    pub fn evaluate(&self, history: &[Infraction], violation: &Infraction) -> StrikeOutcome {
+        let mut counted = history.to_vec();
+        counted.push(violation.clone());
+        let (guild_id, user_id, now) = (
+            &violation.guild_id,
+            &violation.user_id,
+            violation.created_at,
+        );
+        let active_strikes = self.active_strikes(&counted, guild_id, user_id, now);
+        let window_strikes = self.window_strikes(&counted, guild_id, user_id, now);
+
+        let escalation = if self.enabled {
+            self.escalations
+                .iter()
+                .filter(|rung| rung.strikes <= window_strikes)
+                .max_by_key(|rung| rung.strikes)
+                .cloned()
+        } else {
+            None
+        };
+
+        StrikeOutcome {
+            active_strikes,
+            window_strikes,
+            escalation,
+        }
+    }
+}
+
+/// Distinct messages by the member in the `hours` up to `now`. Negative hours
+/// count as zero, and too many to represent as all of history.
+fn strikes_since(
+    history: &[Infraction],
+    guild_id: &str,
+    user_id: &str,
+    now: DateTime<Utc>,
+    hours: i64,
+) -> u32 {
+    let cutoff = Duration::try_hours(hours.max(0))
+        .and_then(|hours| now.checked_sub_signed(hours))
+        .unwrap_or(DateTime::<Utc>::MIN_UTC);
+    history
+        .iter()
+        .filter(|infraction| infraction.guild_id == guild_id && infraction.user_id == user_id)
+        .filter(|infraction| infraction.created_at > cutoff && infraction.created_at <= now)
+        .map(|infraction| infraction.message_id.as_str())
+        .collect::<HashSet<_>>()
+        .len() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn infraction(message_id: &str, label: ModerationLabel, hours_ago: i64) -> Infraction {
+        Infraction {
+            guild_id: "g".to_string(),
+            user_id: "u".to_string(),
+            message_id: message_id.to_string(),
+            label,
+            action: ModerationAction::Delete,
+            created_at: now() - Duration::hours(hours_ago),
+        }
+    }
+
+    fn now() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_760_000_000, 0).unwrap()
+    }
+
+    fn enabled() -> StrikePolicy {
+        StrikePolicy {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn escalates_to_the_highest_rung_reached() {
+        let history = vec![
+            infraction("1", ModerationLabel::T, 5),
+            // Same message, second label: still one strike.
+            infraction("1", ModerationLabel::I, 5),
+            infraction("2", ModerationLabel::T, 1),
+        ];
+        let outcome = enabled().evaluate(&history, &infraction("3", ModerationLabel::T, 0));
+        assert_eq!(outcome.active_strikes, 3);
+        assert_eq!(
+            outcome.escalation.map(|rung| rung.action),
+            Some(ModerationAction::Timeout)
+        );
+
+        let mut history = history;
+        history.push(infraction("3", ModerationLabel::T, 0));
+        history.push(infraction("4", ModerationLabel::T, 0));
+        let outcome = enabled().evaluate(&history, &infraction("5", ModerationLabel::H, 0));
+        assert_eq!(
+            outcome.escalation.map(|rung| rung.action),
+            Some(ModerationAction::Kick)
+        );
+    }
+
+    #[test]
+    fn only_strikes_in_the_window_escalate() {
+        let mut other = infraction("9", ModerationLabel::T, 1);
+        other.user_id = "someone-else".to_string();
+        let history = vec![
+            infraction("1", ModerationLabel::T, 25),
+            infraction("2", ModerationLabel::T, 24),
+            other,
+        ];
+        let outcome = enabled().evaluate(&history, &infraction("3", ModerationLabel::T, 0));
+        // Outside the 24 hour window, but a week from decaying.
+        assert_eq!(outcome.active_strikes, 3);
+        assert_eq!(outcome.window_strikes, 1);
+        assert_eq!(outcome.escalation, None);
+    }
+
+    #[test]
+    fn decayed_strikes_do_not_count_even_inside_the_window() {
+        let policy = StrikePolicy {
+            window_hours: 72,
+            decay_hours: 12,
+            ..enabled()
+        };
+        let history = vec![
+            infraction("1", ModerationLabel::T, 48),
+            infraction("2", ModerationLabel::T, 13),
+            infraction("3", ModerationLabel::T, 2),
+        ];
+        let outcome = policy.evaluate(&history, &infraction("4", ModerationLabel::T, 0));
+        assert_eq!(outcome.active_strikes, 2);
+        assert_eq!(outcome.window_strikes, 2);
+        assert_eq!(outcome.escalation, None);
+
+        let policy = StrikePolicy {
+            decay_hours: 72,
+            ..policy
+        };
+        let outcome = policy.evaluate(&history, &infraction("4", ModerationLabel::T, 0));
+        assert_eq!(outcome.window_strikes, 4);
+        assert_eq!(
+            outcome.escalation.map(|rung| rung.action),
+            Some(ModerationAction::Timeout)
+        );
+    }
+
+    #[test]
+    fn huge_decay_means_strikes_never_decay() {
+        let policy = StrikePolicy {
+            window_hours: i64::MAX,
+            decay_hours: i64::MAX,
+            ..enabled()
+        };
+        let history = vec![infraction("1", ModerationLabel::T, 24 * 365 * 100)];
+        assert_eq!(policy.active_strikes(&history, "g", "u", now()), 1);
+        assert_eq!(policy.window_strikes(&history, "g", "u", now()), 1);
+    }
+
+    #[test]
+    fn disabled_policy_never_escalates() {
+        let history: Vec<Infraction> = (0..10)
+            .map(|i| infraction(&i.to_string(), ModerationLabel::SP, 0))
+            .collect();
+        let outcome =
+            StrikePolicy::default().evaluate(&history, &infraction("x", ModerationLabel::SP, 0));
+        assert_eq!(outcome.active_strikes, 11);
+        assert_eq!(outcome.escalation, None);
+    }
+}
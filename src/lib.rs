@@ -1,11 +1,23 @@
 pub mod admin;
+pub mod api_key;
 pub mod auth;
+pub mod budget;
+pub mod cidr;
 pub mod credits;
 pub mod discord;
 pub mod entitlements;
 pub mod error;
+pub mod forecast;
+pub mod infractions;
+pub mod link_filter;
 pub mod moderate;
+pub mod money;
+pub mod normalize;
 pub mod notifications;
+pub mod org;
 pub mod platform;
 pub mod pricing;
+mod string_enum;
 pub mod user;
+pub mod video;
+pub mod word_filter;
@@ -0,0 +1,500 @@
+//! The link filter a [`GuildConfig`] describes: which URLs in a message are
+//! blocked, and why.
+//!
+//! URLs are found with or without a scheme, so `discord.gg/abc` is caught as
+//! readily as `https://discord.gg/abc`, and inside markdown links and `<...>`
+//! embeds. A host needs a dot and an alphabetic top-level label of two or more
+//! letters, which keeps "e.g" out. A bare domain, with neither a scheme nor
+//! `www.`, must also end in one of [`BARE_DOMAIN_TLDS`], so "main.rs" and
+//! "file.txt" in ordinary chat are not taken for links.
+
+use crate::discord::{GuildConfig, LinkFilterMode};
+use crate::normalize::{NormalizeOptions, skeleton};
+use serde::{Deserialize, Serialize};
+
+/// A URL found in a message. `start..end` is its byte range in the message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExtractedUrl {
+    /// As written, scheme or not.
+    pub url: String,
+    /// Lowercased, without port, credentials or trailing dot.
+    pub host: String,
+    /// Everything after the host, starting at `/`, `?` or `#`. Empty when the
+    /// URL is just a host.
+    pub path: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkKind {
+    /// `discord.gg/...` or `discord.com/invite/...`.
+    DiscordInvite,
+    /// Attachments served from Discord's CDN.
+    DiscordMedia,
+    /// A Nitro gift link, genuine or on a lookalike domain. Free Nitro is the
+    /// commonest phishing lure on Discord, and a real one is
+    /// indistinguishable from a fake to the person clicking it.
+    NitroGift,
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum LinkBlockReason {
+    DiscordInvite,
+    DiscordMedia,
+    NitroGift,
+    /// Matched this entry of `custom_link_filters` in blacklist mode.
+    Blacklisted {
+        rule: String,
+    },
+    /// Matched no entry of `custom_link_filters` in whitelist mode.
+    NotWhitelisted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkVerdict {
+    pub url: ExtractedUrl,
+    pub reason: LinkBlockReason,
+}
+
+/// Domains Discord itself serves from. A host on one of these is never a
+/// lookalike.
+const DISCORD_DOMAINS: &[&str] = &[
+    "discord.com",
+    "discord.gg",
+    "discord.gift",
+    "discord.media",
+    "discord.new",
+    "discord.dev",
+    "discordapp.com",
+    "discordapp.net",
+    "discordstatus.com",
+];
+
+const DISCORD_MEDIA_HOSTS: &[&str] = &[
+    "cdn.discordapp.com",
+    "media.discordapp.net",
+    "cdn.discord.com",
+];
+
+/// Top-level domains a bare domain may end in: the generic ones, the
+/// country codes commonly used for links, and the free ones phishing favours.
+/// Country codes that are also common file extensions (`.rs`, `.md`, `.py`,
+/// `.sh`, `.pl`, `.ps`) and the `.zip` and `.mov` TLDs are left out; with a
+/// scheme or `www.` they are still found.
+pub const BARE_DOMAIN_TLDS: &[&str] = &[
+    "ai", "app", "art", "at", "au", "be", "biz", "blog", "br", "ca", "cc", "cf", "ch", "cloud",
+    "club", "cn", "co", "com", "cz", "de", "dev", "dk", "edu", "es", "eu", "fi", "fr", "fun", "ga",
+    "gg", "gift", "gl", "gov", "gq", "icu", "id", "ie", "in", "info", "io", "it", "jp", "kr",
+    "link", "live", "ly", "me", "ml", "mx", "net", "nl", "no", "nz", "online", "org", "page",
+    "pro", "pt", "ru", "se", "shop", "site", "so", "store", "su", "tech", "tk", "to", "top", "tr",
+    "tv", "ua", "uk", "us", "vip", "vn", "website", "ws", "xyz", "рф",
+];
+
+/// Characters that end a URL candidate. Brackets and quotes are included so
+/// markdown links and `<...>` embeds split cleanly, at the cost of URLs that
+/// contain them.
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || "<>()[]{}\"'`|".contains(c)
+}
+
+/// Trailing characters that are sentence or markdown punctuation rather than
+/// part of the URL.
+const TRAILING: &[char] = &['.', ',', ';', ':', '!', '?', '*', '_', '~'];
+const LEADING: &[char] = &['*', '_', '~'];
+
+/// Every URL in `text`, in order.
+pub fn extract_urls(text: &str) -> Vec<ExtractedUrl> {
+    let mut urls = Vec::new();
+    let mut token_start = None;
+    for (i, c) in text
+        .char_indices()
+        .chain(std::iter::once((text.len(), ' ')))
+    {
+        match (is_delimiter(c), token_start) {
+            (false, None) => token_start = Some(i),
+            (true, Some(start)) => {
+                if let Some(url) = parse_candidate(text, start, i) {
+                    urls.push(url);
+                }
+                token_start = None;
+            }
+            _ => {}
+        }
+    }
+    urls
+}
+
+fn parse_candidate(text: &str, start: usize, end: usize) -> Option<ExtractedUrl> {
+    let raw = &text[start..end];
+    let trimmed_start = raw.trim_start_matches(LEADING);
+    let start = start + (raw.len() - trimmed_start.len());
+    let candidate = trimmed_start.trim_end_matches(TRAILING);
+    let end = start + candidate.len();
+
+    // Everything is split on the original text, and only the host is
+    // lowercased: lowercasing can change byte lengths, so offsets into a
+    // lowercased copy would not fit the original.
+    let (rest, has_scheme) = if let Some(rest) = strip_prefix_ignore_case(candidate, "https://") {
+        (rest, true)
+    } else if let Some(rest) = strip_prefix_ignore_case(candidate, "http://") {
+        (rest, true)
+    } else if candidate.contains("://") || candidate.contains('@') {
+        // Another scheme, or an email address.
+        return None;
+    } else {
+        (candidate, false)
+    };
+
+    let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(authority_end);
+    let host_port = authority.rsplit('@').next().unwrap_or(authority);
+    let host = match host_port.rsplit_once(':') {
+        Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+        _ => host_port,
+    };
+    let host = host.trim_end_matches('.').to_lowercase();
+    if !is_valid_host(&host, has_scheme) {
+        return None;
+    }
+    if !has_scheme && !host.starts_with("www.") {
+        let tld = host.rsplit('.').next().unwrap_or_default();
+        if !BARE_DOMAIN_TLDS.contains(&tld) {
+            return None;
+        }
+    }
+
+    Some(ExtractedUrl {
+        url: candidate.to_string(),
+        host,
+        path: path.to_string(),
+        start,
+        end,
+    })
+}
+
+fn strip_prefix_ignore_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    text.get(..prefix.len())
+        .filter(|head| head.eq_ignore_ascii_case(prefix))
+        .map(|_| &text[prefix.len()..])
+}
+
+fn is_valid_host(host: &str, has_scheme: bool) -> bool {
+    let labels: Vec<&str> = host.split('.').collect();
+    if labels.len() < 2 {
+        return false;
+    }
+    let well_formed = labels.iter().all(|label| {
+        !label.is_empty()
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+    });
+    if !well_formed {
+        return false;
+    }
+    let tld = labels[labels.len() - 1];
+    let is_ipv4 = labels.len() == 4 && host.parse::<std::net::Ipv4Addr>().is_ok();
+    (tld.chars().count() >= 2 && tld.chars().all(char::is_alphabetic)) || (has_scheme && is_ipv4)
+}
+
+fn is_on_domain(host: &str, domain: &str) -> bool {
+    host == domain
+        || host
+            .strip_suffix(domain)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+pub fn classify(url: &ExtractedUrl) -> LinkKind {
+    let host = url.host.as_str();
+    let path = url.path.to_lowercase();
+    let on_discord_app = is_on_domain(host, "discord.com") || is_on_domain(host, "discordapp.com");
+
+    if host == "discord.gg" || (on_discord_app && path.starts_with("/invite/")) {
+        return LinkKind::DiscordInvite;
+    }
+    if DISCORD_MEDIA_HOSTS.contains(&host) {
+        return LinkKind::DiscordMedia;
+    }
+    if host == "discord.gift" || (on_discord_app && path.starts_with("/gifts/")) {
+        return LinkKind::NitroGift;
+    }
+    if is_nitro_lookalike(host) {
+        return LinkKind::NitroGift;
+    }
+    LinkKind::Other
+}
+
+/// A non-Discord host that names Discord, however misspelt, alongside Nitro or
+/// a gift: `discord-nitro.com`, `dlscord-gift.ru`, `disc0rdnitro.xyz`.
+fn is_nitro_lookalike(host: &str) -> bool {
+    if DISCORD_DOMAINS
+        .iter()
+        .any(|domain| is_on_domain(host, domain))
+    {
+        return false;
+    }
+    let folded: String = host
+        .chars()
+        .map(|c| match c {
+            '1' | 'l' => 'i',
+            '0' => 'o',
+            _ => c,
+        })
+        .collect();
+    folded.contains("discord")
+        && (folded.contains("nitro") || folded.contains("gift") || folded.contains("giveaway"))
+}
+
+/// A domain rule from `custom_link_filters`, reduced to a bare host: the
+/// scheme, path, a leading `*.` and a leading `www.` are dropped. It matches
+/// that host and every subdomain of it.
+fn normalize_rule(rule: &str) -> Option<String> {
+    let rule = rule.trim().to_lowercase();
+    let rule = rule
+        .split_once("://")
+        .map_or(rule.as_str(), |(_, rest)| rest);
+    let rule = rule.split(['/', '?', '#']).next().unwrap_or_default();
+    let rule = rule.strip_prefix("*.").unwrap_or(rule);
+    let rule = rule.strip_prefix("www.").unwrap_or(rule);
+    let rule = rule.trim_end_matches('.');
+    (!rule.is_empty()).then(|| rule.to_string())
+}
+
+/// A guild's link settings, compiled. Build once per config change.
+#[derive(Debug, Clone)]
+pub struct LinkFilter {
+    enabled: bool,
+    block_invites: bool,
+    block_media: bool,
+    block_nitro: bool,
+    mode: LinkFilterMode,
+    /// (as configured, normalized)
+    rules: Vec<(String, String)>,
+}
+
+impl LinkFilter {
+    pub fn from_config(config: &GuildConfig) -> Self {
+        Self {
+            enabled: config.enable_link_filter,
+            block_invites: config.block_discord_invites,
+            block_media: config.block_discord_media,
+            block_nitro: config.block_discord_nitro,
+            mode: config.link_filter_mode.clone(),
+            rules: config
+                .custom_link_filters
+                .iter()
+                .filter_map(|rule| {
+                    normalize_rule(rule).map(|normalized| (rule.clone(), normalized))
+                })
+                .collect(),
+        }
+    }
+
+    /// Why `url` is blocked, or `None` when it may stay.
+    ///
+    /// The Discord toggles are checked first and apply in either mode. A
+    /// Discord link they let through is then treated like any other, so in
+    /// whitelist mode it still has to be on the list.
+    pub fn judge(&self, url: &ExtractedUrl) -> Option<LinkBlockReason> {
+        if !self.enabled {
+            return None;
+        }
+        match classify(url) {
+            LinkKind::DiscordInvite if self.block_invites => {
+                return Some(LinkBlockReason::DiscordInvite);
+            }
+            LinkKind::DiscordMedia if self.block_media => {
+                return Some(LinkBlockReason::DiscordMedia);
+            }
+            LinkKind::NitroGift if self.block_nitro => return Some(LinkBlockReason::NitroGift),
+            _ => {}
+        }
+
+        let matched = self
+            .rules
+            .iter()
+            .find(|(_, normalized)| is_on_domain(&url.host, normalized));
+        match (&self.mode, matched) {
+            (LinkFilterMode::Blacklist, Some((rule, _))) => {
+                Some(LinkBlockReason::Blacklisted { rule: rule.clone() })
+            }
+            (LinkFilterMode::Whitelist, None) => Some(LinkBlockReason::NotWhitelisted),
+            _ => None,
+        }
+    }
+
+    /// Every blocked URL in `text`, in order.
+    pub fn check_all(&self, text: &str) -> Vec<LinkVerdict> {
+        if !self.enabled {
+            return Vec::new();
+        }
+        self.judge_all(extract_urls(text))
+    }
+
+    /// Like [`LinkFilter::check_all`], but URLs are found in the normalized
+    /// skeleton of `text`, so fullwidth letters, homoglyphs and zero-width
+    /// characters do not hide a domain. Each verdict's `url` and span are the
+    /// original text; its host is the normalized one the rules were applied
+    /// to.
+    pub fn check_all_normalized(&self, text: &str) -> Vec<LinkVerdict> {
+        if !self.enabled {
+            return Vec::new();
+        }
+        let skeleton = skeleton(text, &NormalizeOptions::links());
+        let urls = extract_urls(&skeleton.text)
+            .into_iter()
+            .map(|mut url| {
+                let original = skeleton.original_range(url.start..url.end);
+                url.url = text[original.clone()].to_string();
+                url.start = original.start;
+                url.end = original.end;
+                url
+            })
+            .collect();
+        self.judge_all(urls)
+    }
+
+    fn judge_all(&self, urls: Vec<ExtractedUrl>) -> Vec<LinkVerdict> {
+        urls.into_iter()
+            .filter_map(|url| self.judge(&url).map(|reason| LinkVerdict { url, reason }))
+            .collect()
+    }
+
+    /// The first blocked URL in `text`, which is all the bot needs to act.
+    pub fn check(&self, text: &str) -> Option<LinkVerdict> {
+        self.check_all(text).into_iter().next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hosts(text: &str) -> Vec<String> {
+        extract_urls(text).into_iter().map(|url| url.host).collect()
+    }
+
+    #[test]
+    fn extraction() {
+        assert_eq!(
+            hosts("see https://Example.com/a?b=1, and www.test.co.uk."),
+            vec!["example.com", "www.test.co.uk"]
+        );
+        assert_eq!(
+            hosts("[click here](https://evil.com/x) <https://quiet.net>"),
+            vec!["evil.com", "quiet.net"]
+        );
+        assert_eq!(hosts("join discord.gg/abc **now**"), vec!["discord.gg"]);
+        assert_eq!(
+            hosts("http://user:pw@host.io:8080/p http://10.0.0.1/"),
+            vec!["host.io", "10.0.0.1"]
+        );
+        // Not URLs: abbreviations, emails, other schemes, bare IPs, no dot.
+        assert!(hosts("e.g. me@mail.com ftp://x.org 10.0.0.1 localhost").is_empty());
+        // Bare filenames are not domains; with a scheme or `www.` they are.
+        assert!(hosts("edit main.rs and README.md, then file.txt").is_empty());
+        assert_eq!(
+            hosts("https://main.rs www.file.txt"),
+            vec!["main.rs", "www.file.txt"]
+        );
+    }
+
+    #[test]
+    fn spans_and_paths_keep_the_original_text() {
+        let text = "go to *https://x.com/AbC*!";
+        let url = &extract_urls(text)[0];
+        assert_eq!(&text[url.start..url.end], "https://x.com/AbC");
+        assert_eq!(url.path, "/AbC");
+
+        // 'İ' grows when lowercased, so offsets into a lowercased copy drift.
+        let url = &extract_urls("x.com/İİ")[0];
+        assert_eq!(url.path, "/İİ");
+        let url = &extract_urls("http://a.bc/İİİİİİ")[0];
+        assert_eq!((url.host.as_str(), url.path.as_str()), ("a.bc", "/İİİİİİ"));
+        let text = "see HTTPS://Straße.Example.com/Ş?q=İ ok";
+        let url = &extract_urls(text)[0];
+        assert_eq!(url.host, "straße.example.com");
+        assert_eq!(url.path, "/Ş?q=İ");
+        assert_eq!(
+            &text[url.start..url.end],
+            "HTTPS://Straße.Example.com/Ş?q=İ"
+        );
+    }
+
+    #[test]
+    fn classification() {
+        let kind = |text: &str| classify(&extract_urls(text)[0]);
+        assert_eq!(kind("discord.gg/abc"), LinkKind::DiscordInvite);
+        assert_eq!(
+            kind("https://discord.com/invite/abc"),
+            LinkKind::DiscordInvite
+        );
+        assert_eq!(
+            kind("https://ptb.discord.com/invite/abc"),
+            LinkKind::DiscordInvite
+        );
+        assert_eq!(kind("https://discord.com/channels/1/2"), LinkKind::Other);
+        assert_eq!(
+            kind("https://cdn.discordapp.com/attachments/1/2/a.png"),
+            LinkKind::DiscordMedia
+        );
+        assert_eq!(kind("https://discord.gift/abc"), LinkKind::NitroGift);
+        assert_eq!(kind("https://discord.com/gifts/abc"), LinkKind::NitroGift);
+        assert_eq!(kind("dlscord-nitro.ru/claim"), LinkKind::NitroGift);
+        assert_eq!(kind("https://disc0rd-gift.com"), LinkKind::NitroGift);
+        assert_eq!(kind("https://nitro.example.com"), LinkKind::Other);
+    }
+
+    #[test]
+    fn blacklist_matches_subdomains_not_suffixes() {
+        let filter = LinkFilter::from_config(&GuildConfig {
+            custom_link_filters: vec!["https://www.Bad.com/path".to_string()],
+            ..Default::default()
+        });
+        let reason = |text: &str| filter.check(text).map(|verdict| verdict.reason);
+        let blocked = Some(LinkBlockReason::Blacklisted {
+            rule: "https://www.Bad.com/path".to_string(),
+        });
+        assert_eq!(reason("bad.com"), blocked);
+        assert_eq!(reason("https://cdn.bad.com/x"), blocked);
+        assert_eq!(reason("notbad.com"), None);
+        // Invites are blocked by default in either mode.
+        assert_eq!(reason("discord.gg/x"), Some(LinkBlockReason::DiscordInvite));
+    }
+
+    #[test]
+    fn normalized_check_finds_disguised_invites() {
+        let filter = LinkFilter::from_config(&GuildConfig::default());
+        let text = "join ｄｉｓｃ\u{200B}\u{043E}rd.gg/abc";
+        assert!(filter.check(text).is_none());
+        let verdict = filter.check_all_normalized(text).remove(0);
+        assert_eq!(verdict.reason, LinkBlockReason::DiscordInvite);
+        assert_eq!(verdict.url.host, "discord.gg");
+        assert_eq!(&text[verdict.url.start..verdict.url.end], &text[5..]);
+        assert_eq!(verdict.url.url, &text[5..]);
+    }
+
+    #[test]
+    fn whitelist_blocks_everything_else() {
+        let mut config = GuildConfig {
+            link_filter_mode: LinkFilterMode::Whitelist,
+            custom_link_filters: vec!["*.youtube.com".to_string(), "discord.gg".to_string()],
+            block_discord_invites: false,
+            ..Default::default()
+        };
+        let filter = LinkFilter::from_config(&config);
+        assert_eq!(filter.check("https://m.youtube.com/watch"), None);
+        assert_eq!(filter.check("discord.gg/abc"), None);
+        let verdict = filter.check("ok youtube.com then evil.io").unwrap();
+        assert_eq!(verdict.url.host, "evil.io");
+        assert_eq!(verdict.reason, LinkBlockReason::NotWhitelisted);
+        assert_eq!(filter.check("edit main.rs and lib.rs"), None);
+
+        config.enable_link_filter = false;
+        assert!(LinkFilter::from_config(&config).check("evil.io").is_none());
+    }
+}
@@ -1,5 +1,7 @@
+use crate::error::ParseEnumError;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fmt::Display;
 use std::str::FromStr;
 
@@ -17,6 +19,10 @@ pub struct ModerationRequest {
     pub include_context: bool,
     #[serde(default)]
     pub include_implicit: bool,
+    /// Per-label score a label must reach to be flagged. Labels left out use
+    /// [`DEFAULT_LABEL_THRESHOLD`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thresholds: Option<HashMap<ModerationLabel, f32>>,
 }
 
 /// A short video to moderate. Keyframes are extracted and run through the same
@@ -74,6 +80,10 @@ pub struct BatchModerationRequest {
     pub include_context: bool,
     #[serde(default)]
     pub include_implicit: bool,
+    /// Per-label score a label must reach to be flagged. Labels left out use
+    /// [`DEFAULT_LABEL_THRESHOLD`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thresholds: Option<HashMap<ModerationLabel, f32>>,
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModerationResponse {
@@ -93,6 +103,46 @@ pub struct ModerationResponse {
     /// Shown on flagged-image alerts and used for image moderation feedback.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub extracted_text: Option<String>,
+    /// Confidence per label, 0.0 to 1.0. Lets a caller see borderline calls
+    /// and re-threshold a cached response with [`ModerationResponse::apply_thresholds`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scores: Option<HashMap<ModerationLabel, f32>>,
+}
+
+/// Score a label has to reach to be flagged when the caller sets no threshold
+/// of its own.
+pub const DEFAULT_LABEL_THRESHOLD: f32 = 0.5;
+
+impl ModerationResponse {
+    /// Re-derive `labels` and `flagged` from `scores` against `thresholds`,
+    /// falling back to [`DEFAULT_LABEL_THRESHOLD`] for labels without one.
+    /// Only `enabled_labels` can be flagged, as with the request field of the
+    /// same name; `None` enables every label.
+    ///
+    /// Returns false, leaving the response untouched, when it carries no
+    /// scores. Labels come out in [`ModerationLabel::all_labels`] order.
+    pub fn apply_thresholds(
+        &mut self,
+        thresholds: &HashMap<ModerationLabel, f32>,
+        enabled_labels: Option<&[ModerationLabel]>,
+    ) -> bool {
+        let Some(scores) = &self.scores else {
+            return false;
+        };
+        self.labels = ModerationLabel::all_labels()
+            .into_iter()
+            .filter(|label| enabled_labels.is_none_or(|enabled| enabled.contains(label)))
+            .filter(|label| {
+                let threshold = thresholds
+                    .get(label)
+                    .copied()
+                    .unwrap_or(DEFAULT_LABEL_THRESHOLD);
+                scores.get(label).is_some_and(|score| *score >= threshold)
+            })
+            .collect();
+        self.flagged = !self.labels.is_empty();
+        true
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -192,10 +242,13 @@ impl Display for ModerationLabel {
 }
 
 impl FromStr for ModerationLabel {
-    type Err = ();
+    type Err = ParseEnumError;
 
+    /// Accepts the short codes and the long names serde reads, both
+    /// case-insensitively, so anything valid in a config file is valid here
+    /// too.
     fn from_str(input: &str) -> Result<ModerationLabel, Self::Err> {
-        match input {
+        match input.to_ascii_uppercase().as_str() {
             "P" => Ok(ModerationLabel::P),
             "T" => Ok(ModerationLabel::T),
             "H" => Ok(ModerationLabel::H),
@@ -212,13 +265,75 @@ impl FromStr for ModerationLabel {
             "PM" => Ok(ModerationLabel::PM),
             "SI" => Ok(ModerationLabel::SI),
             "IL" => Ok(ModerationLabel::IL),
-            _ => Err(()),
+            _ => ModerationLabel::from_long_name(input),
+        }
+    }
+}
+
+impl ModerationLabel {
+    fn from_long_name(input: &str) -> Result<ModerationLabel, ParseEnumError> {
+        match input.to_lowercase().as_str() {
+            "profanity" => Ok(ModerationLabel::P),
+            "toxicity" => Ok(ModerationLabel::T),
+            "harassment" => Ok(ModerationLabel::H),
+            "hate" => Ok(ModerationLabel::HR),
+            "insult" => Ok(ModerationLabel::I),
+            "sexual" => Ok(ModerationLabel::S),
+            "sexual/unlawful" | "sexual/minors" => Ok(ModerationLabel::SU),
+            "sexual/explicit" => Ok(ModerationLabel::S2),
+            "sensitive" => Ok(ModerationLabel::SE),
+            "violence" => Ok(ModerationLabel::V),
+            "self-harm" => Ok(ModerationLabel::SH),
+            "medical" => Ok(ModerationLabel::M),
+            "spam" => Ok(ModerationLabel::SP),
+            "promotional" => Ok(ModerationLabel::PM),
+            "scam" => Ok(ModerationLabel::SI),
+            "illegal" => Ok(ModerationLabel::IL),
+            _ => Err(ParseEnumError::new(
+                input,
+                "ModerationLabel",
+                &[
+                    "P",
+                    "T",
+                    "H",
+                    "HR",
+                    "I",
+                    "S",
+                    "SU",
+                    "S2",
+                    "SE",
+                    "V",
+                    "SH",
+                    "M",
+                    "SP",
+                    "PM",
+                    "SI",
+                    "IL",
+                    "profanity",
+                    "toxicity",
+                    "harassment",
+                    "hate",
+                    "insult",
+                    "sexual",
+                    "sexual/unlawful",
+                    "sexual/minors",
+                    "sexual/explicit",
+                    "sensitive",
+                    "violence",
+                    "self-harm",
+                    "medical",
+                    "spam",
+                    "promotional",
+                    "scam",
+                    "illegal",
+                ],
+            )),
         }
     }
 }
 
 impl FromStr for ModerationModel {
-    type Err = ();
+    type Err = ParseEnumError;
 
     fn from_str(input: &str) -> Result<ModerationModel, Self::Err> {
         match input.to_lowercase().as_str() {
@@ -226,7 +341,11 @@ impl FromStr for ModerationModel {
             "observer" => Ok(ModerationModel::Observer),
             "sentinel" => Ok(ModerationModel::Sentinel),
             "arbiter" => Ok(ModerationModel::Arbiter),
-            _ => Err(()),
+            _ => Err(ParseEnumError::new(
+                input,
+                "ModerationModel",
+                &["auto", "observer", "sentinel", "arbiter"],
+            )),
         }
     }
 }
@@ -332,14 +451,22 @@ impl ModerationModel {
         format!("{} {}", self.base_name(), self.version())
     }
 
+    /// Panics on `Auto`; see [`ModerationModel::checked_credits_per_byte`].
     pub fn credits_per_byte(&self) -> i64 {
+        match self.checked_credits_per_byte() {
+            Some(rate) => rate,
+            None => panic!("Auto must be resolved before calculating credits"),
+        }
+    }
+
+    /// The text rate, or `None` for `Auto`, which has no rate until
+    /// [`ModerationModel::resolve_auto`] picks a model.
+    pub fn checked_credits_per_byte(&self) -> Option<i64> {
         match self {
-            ModerationModel::Auto => {
-                panic!("Auto must be resolved before calculating credits")
-            }
-            ModerationModel::Observer => 1,
-            ModerationModel::Sentinel => 2,
-            ModerationModel::Arbiter => 4,
+            ModerationModel::Auto => None,
+            ModerationModel::Observer => Some(1),
+            ModerationModel::Sentinel => Some(2),
+            ModerationModel::Arbiter => Some(4),
         }
     }
 
@@ -363,6 +490,7 @@ impl ModerationModel {
         }
 
         // Sort by credits_per_byte descending (best/most expensive first)
+        #[allow(clippy::unnecessary_sort_by)]
         concrete.sort_by(|a, b| b.credits_per_byte().cmp(&a.credits_per_byte()));
 
         let n = concrete.len() as i64;
@@ -393,11 +521,169 @@ impl ModerationModel {
     }
 }
 
+/// What a line of a [`CostEstimate`] is billed as.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum CostItemKind {
+    Text,
+    Image,
+    Video,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CostLineItem {
+    pub kind: CostItemKind,
+    /// Bytes billed: UTF-8 length for text, decoded length for base64 media.
+    pub bytes: i64,
+    pub credits: i64,
+}
+
+/// Credits a request will cost, worked out from the same constants the API
+/// bills with, so the SDKs, the bot and the dashboard stop carrying their own
+/// copies of the maths.
+///
+/// This is a floor, not a quote: text the OCR finds in an image is billed on
+/// top at the model's rate once it has been extracted.
+///
+/// Every constructor returns `None` for [`ModerationModel::Auto`], which has no
+/// price until [`ModerationModel::resolve_auto`] turns it into a concrete
+/// model.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CostEstimate {
+    pub model: ModerationModel,
+    pub items: Vec<CostLineItem>,
+    /// Sum of the line items before any batch discount.
+    pub subtotal: i64,
+    pub batch_discount: i64,
+    pub total: i64,
+}
+
+impl CostEstimate {
+    pub fn for_request(request: &ModerationRequest, model: &ModerationModel) -> Option<Self> {
+        let rate = model.checked_credits_per_byte()?;
+        let mut items = Vec::new();
+        if let Some(text) = &request.text {
+            items.push(text_item(text, rate));
+        }
+        if let Some(image) = &request.image {
+            items.push(image_item(image));
+        }
+        Some(Self::from_items(model, items, false))
+    }
+
+    /// The discount is applied once to the batch subtotal, and only from
+    /// [`MIN_BATCH_SIZE_FOR_DISCOUNT`] items up.
+    pub fn for_batch(request: &BatchModerationRequest, model: &ModerationModel) -> Option<Self> {
+        let rate = model.checked_credits_per_byte()?;
+        let items: Vec<CostLineItem> = request
+            .texts
+            .iter()
+            .map(|text| text_item(text, rate))
+            .chain(request.images.iter().map(|image| image_item(image)))
+            .collect();
+        let discounted = items.len() >= MIN_BATCH_SIZE_FOR_DISCOUNT;
+        Some(Self::from_items(model, items, discounted))
+    }
+
+    /// A video is billed per moderated frame, not by the size of the clip.
+    /// `frames` are the base64 JPEG frames extracted from `request.video` and
+    /// deduplicated under the frame contract ([`crate::video::dedup_frames`]);
+    /// past [`MAX_VIDEO_FRAMES`] they are not moderated, so not billed.
+    ///
+    /// The frames go through the batch endpoint together, so the batch
+    /// discount applies to them exactly as it does in
+    /// [`CostEstimate::for_batch`]: from [`MIN_BATCH_SIZE_FOR_DISCOUNT`]
+    /// frames up. `None` as well for a clip over [`MAX_VIDEO_BYTES`], which
+    /// is refused before anything is billed.
+    pub fn for_video<S: AsRef<str>>(
+        request: &VideoModerationRequest,
+        frames: &[S],
+        model: &ModerationModel,
+    ) -> Option<Self> {
+        if model.is_auto() || base64_decoded_len(&request.video) > MAX_VIDEO_BYTES {
+            return None;
+        }
+        let items: Vec<CostLineItem> = frames
+            .iter()
+            .take(MAX_VIDEO_FRAMES)
+            .map(|frame| {
+                let bytes = base64_decoded_len(frame.as_ref());
+                CostLineItem {
+                    kind: CostItemKind::Video,
+                    bytes,
+                    credits: bytes * IMAGE_CREDITS_PER_BYTE * VIDEO_FRAME_CREDITS_PER_BYTE_PERCENT
+                        / 100,
+                }
+            })
+            .collect();
+        let discounted = items.len() >= MIN_BATCH_SIZE_FOR_DISCOUNT;
+        Some(Self::from_items(model, items, discounted))
+    }
+
+    fn from_items(model: &ModerationModel, items: Vec<CostLineItem>, discounted: bool) -> Self {
+        let subtotal: i64 = items.iter().map(|item| item.credits).sum();
+        let batch_discount = if discounted {
+            subtotal * BATCH_DISCOUNT_PERCENT / 100
+        } else {
+            0
+        };
+        Self {
+            model: model.clone(),
+            items,
+            subtotal,
+            batch_discount,
+            total: subtotal - batch_discount,
+        }
+    }
+}
+
+fn text_item(text: &str, credits_per_byte: i64) -> CostLineItem {
+    let bytes = text.len() as i64;
+    CostLineItem {
+        kind: CostItemKind::Text,
+        bytes,
+        credits: bytes * credits_per_byte,
+    }
+}
+
+fn image_item(image: &str) -> CostLineItem {
+    let bytes = base64_decoded_len(image);
+    CostLineItem {
+        kind: CostItemKind::Image,
+        bytes,
+        credits: bytes * IMAGE_CREDITS_PER_BYTE,
+    }
+}
+
+/// Decoded length of a base64 payload, ignoring any `data:...;base64,` prefix,
+/// without decoding it.
+fn base64_decoded_len(data: &str) -> i64 {
+    let payload = match data.split_once(";base64,") {
+        Some((prefix, rest)) if prefix.starts_with("data:") => rest,
+        _ => data,
+    };
+    let significant = payload
+        .bytes()
+        .filter(|b| !b.is_ascii_whitespace() && *b != b'=')
+        .count();
+    (significant * 3 / 4) as i64
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UsernameCheckRequest {
     pub username: String,
 }
 
+impl UsernameCheckRequest {
+    /// The username with obfuscation folded away, which is what should be
+    /// scored: display names are where homoglyph and leetspeak evasion is most
+    /// common.
+    pub fn normalized_username(&self) -> String {
+        crate::normalize::skeleton(&self.username, &crate::normalize::NormalizeOptions::words())
+            .text
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UsernameCheckResponse {
     pub flagged: bool,
@@ -454,6 +740,69 @@ impl ModerationLabel {
         }
     }
 
+    pub fn category(&self) -> LabelCategory {
+        match self {
+            ModerationLabel::P | ModerationLabel::T | ModerationLabel::I => LabelCategory::Toxicity,
+            ModerationLabel::H | ModerationLabel::HR => LabelCategory::Harassment,
+            ModerationLabel::S | ModerationLabel::S2 | ModerationLabel::SU => LabelCategory::Sexual,
+            ModerationLabel::V | ModerationLabel::SH => LabelCategory::Violence,
+            ModerationLabel::SE | ModerationLabel::M => LabelCategory::Sensitive,
+            ModerationLabel::SP | ModerationLabel::PM | ModerationLabel::SI => LabelCategory::Spam,
+            ModerationLabel::IL => LabelCategory::Illegal,
+        }
+    }
+
+    pub fn severity(&self) -> LabelSeverity {
+        match self {
+            ModerationLabel::P
+            | ModerationLabel::I
+            | ModerationLabel::SE
+            | ModerationLabel::M
+            | ModerationLabel::SP
+            | ModerationLabel::PM => LabelSeverity::Low,
+            ModerationLabel::T | ModerationLabel::H | ModerationLabel::S | ModerationLabel::SI => {
+                LabelSeverity::Medium
+            }
+            ModerationLabel::HR
+            | ModerationLabel::S2
+            | ModerationLabel::V
+            | ModerationLabel::SH
+            | ModerationLabel::IL => LabelSeverity::High,
+            ModerationLabel::SU => LabelSeverity::Critical,
+        }
+    }
+
+    /// The broader label this one refines, e.g. explicit and unlawful sexual
+    /// content are both kinds of `S`. Disabling a parent in a UI should
+    /// disable its children with it.
+    pub fn parent(&self) -> Option<ModerationLabel> {
+        match self {
+            ModerationLabel::S2 | ModerationLabel::SU => Some(ModerationLabel::S),
+            ModerationLabel::HR => Some(ModerationLabel::H),
+            _ => None,
+        }
+    }
+
+    /// Content that may have to be reported to the authorities rather than
+    /// just removed. Never suppress it from alerts, whatever the guild's
+    /// settings.
+    pub fn is_legally_reportable(&self) -> bool {
+        matches!(self, ModerationLabel::SU)
+    }
+
+    /// Most severe first, then in [`ModerationLabel::all_labels`] order, so
+    /// alerts list labels the same way wherever they are rendered.
+    pub fn sort_by_severity(labels: &mut [ModerationLabel]) {
+        labels.sort_by_key(|label| (std::cmp::Reverse(label.severity()), label.ordinal()));
+    }
+
+    fn ordinal(&self) -> usize {
+        ModerationLabel::all_labels()
+            .iter()
+            .position(|label| label == self)
+            .unwrap_or(usize::MAX)
+    }
+
     pub fn all_labels() -> Vec<ModerationLabel> {
         vec![
             ModerationLabel::P,
@@ -475,3 +824,313 @@ impl ModerationLabel {
         ]
     }
 }
+
+/// How bad a label is, ordered so `max()` gives the worst of a set.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelSeverity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+/// Families of labels, for grouping toggles in the dashboard and lines in an
+/// alert. Declared in display order.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelCategory {
+    Toxicity,
+    Harassment,
+    Sexual,
+    Violence,
+    Sensitive,
+    Spam,
+    Illegal,
+}
+
+impl LabelCategory {
+    pub fn all_categories() -> Vec<LabelCategory> {
+        vec![
+            LabelCategory::Toxicity,
+            LabelCategory::Harassment,
+            LabelCategory::Sexual,
+            LabelCategory::Violence,
+            LabelCategory::Sensitive,
+            LabelCategory::Spam,
+            LabelCategory::Illegal,
+        ]
+    }
+
+    pub fn to_name(&self) -> &str {
+        match self {
+            LabelCategory::Toxicity => "Toxicity",
+            LabelCategory::Harassment => "Harassment & Hate",
+            LabelCategory::Sexual => "Sexual Content",
+            LabelCategory::Violence => "Violence & Self-Harm",
+            LabelCategory::Sensitive => "Sensitive & Medical",
+            LabelCategory::Spam => "Spam & Scams",
+            LabelCategory::Illegal => "Illegal Activity",
+        }
+    }
+
+    /// Labels in this category, in [`ModerationLabel::all_labels`] order.
+    pub fn labels(&self) -> Vec<ModerationLabel> {
+        ModerationLabel::all_labels()
+            .into_iter()
+            .filter(|label| label.category() == *self)
+            .collect()
+    }
+
+    /// Group `labels` by category, categories in display order and labels in
+    /// [`ModerationLabel::all_labels`] order. Empty categories are left out.
+    pub fn group<'a>(
+        labels: impl IntoIterator<Item = &'a ModerationLabel>,
+    ) -> Vec<(LabelCategory, Vec<ModerationLabel>)> {
+        let present: Vec<&ModerationLabel> = labels.into_iter().collect();
+        LabelCategory::all_categories()
+            .into_iter()
+            .filter_map(|category| {
+                let members: Vec<ModerationLabel> = category
+                    .labels()
+                    .into_iter()
+                    .filter(|label| present.contains(&label))
+                    .collect();
+                (!members.is_empty()).then_some((category, members))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch(texts: &[&str], images: &[&str]) -> BatchModerationRequest {
+        BatchModerationRequest {
+            texts: texts.iter().map(|t| t.to_string()).collect(),
+            images: images.iter().map(|i| i.to_string()).collect(),
+            model: None,
+            enabled_labels: None,
+            include_context: false,
+            include_implicit: false,
+            thresholds: None,
+        }
+    }
+
+    #[test]
+    fn single_request_bills_text_at_model_rate_and_image_flat() {
+        let request = ModerationRequest {
+            text: Some("hello".to_string()),
+            // 8 base64 chars decode to 6 bytes; the prefix is not billed.
+            image: Some("data:image/png;base64,AAAAAAAA".to_string()),
+            model: None,
+            enabled_labels: None,
+            include_context: false,
+            include_implicit: false,
+            thresholds: None,
+        };
+        let estimate = CostEstimate::for_request(&request, &ModerationModel::Arbiter).unwrap();
+        assert_eq!(estimate.items[0].credits, 5 * 4);
+        assert_eq!(estimate.items[1].bytes, 6);
+        assert_eq!(estimate.items[1].credits, 6);
+        assert_eq!(estimate.batch_discount, 0);
+        assert_eq!(estimate.total, 26);
+    }
+
+    #[test]
+    fn batch_of_one_is_not_discounted() {
+        let estimate =
+            CostEstimate::for_batch(&batch(&["hello"], &[]), &ModerationModel::Sentinel).unwrap();
+        assert_eq!(estimate.total, 10);
+
+        let estimate =
+            CostEstimate::for_batch(&batch(&["hello", "world"], &[]), &ModerationModel::Sentinel)
+                .unwrap();
+        assert_eq!(estimate.subtotal, 20);
+        assert_eq!(estimate.batch_discount, 10);
+        assert_eq!(estimate.total, 10);
+    }
+
+    #[test]
+    fn padding_is_not_billed() {
+        assert_eq!(base64_decoded_len("AAAA"), 3);
+        assert_eq!(base64_decoded_len("AAA="), 2);
+        assert_eq!(base64_decoded_len("AA=="), 1);
+    }
+
+    fn scored(scores: &[(ModerationLabel, f32)]) -> ModerationResponse {
+        ModerationResponse {
+            flagged: false,
+            labels: Vec::new(),
+            implicit_labels: None,
+            model_version: None,
+            needs_context: None,
+            context_labels: None,
+            rewritten_text: None,
+            extracted_text: None,
+            scores: Some(scores.iter().cloned().collect()),
+        }
+    }
+
+    #[test]
+    fn rethresholding_uses_default_for_unlisted_labels() {
+        let mut response = scored(&[
+            (ModerationLabel::T, 0.55),
+            (ModerationLabel::SP, 0.3),
+            (ModerationLabel::H, 0.5),
+        ]);
+        assert!(response.apply_thresholds(&HashMap::new(), None));
+        assert_eq!(
+            response.labels,
+            vec![ModerationLabel::T, ModerationLabel::H]
+        );
+        assert!(response.flagged);
+
+        let strict = HashMap::from([(ModerationLabel::T, 0.9), (ModerationLabel::H, 0.9)]);
+        assert!(response.apply_thresholds(&strict, None));
+        assert!(response.labels.is_empty());
+        assert!(!response.flagged);
+    }
+
+    #[test]
+    fn rethresholding_never_flags_disabled_labels() {
+        let mut response = scored(&[(ModerationLabel::T, 0.9), (ModerationLabel::SP, 0.9)]);
+        assert!(response.apply_thresholds(&HashMap::new(), Some(&[ModerationLabel::SP])));
+        assert_eq!(response.labels, vec![ModerationLabel::SP]);
+
+        assert!(response.apply_thresholds(&HashMap::new(), Some(&[])));
+        assert!(response.labels.is_empty());
+        assert!(!response.flagged);
+    }
+
+    #[test]
+    fn rethresholding_without_scores_changes_nothing() {
+        let mut response = scored(&[]);
+        response.scores = None;
+        response.flagged = true;
+        response.labels = vec![ModerationLabel::SP];
+        assert!(!response.apply_thresholds(&HashMap::new(), None));
+        assert_eq!(response.labels, vec![ModerationLabel::SP]);
+        assert!(response.flagged);
+    }
+
+    #[test]
+    fn every_label_belongs_to_exactly_one_category() {
+        let grouped: Vec<ModerationLabel> = LabelCategory::all_categories()
+            .iter()
+            .flat_map(|category| category.labels())
+            .collect();
+        assert_eq!(grouped.len(), ModerationLabel::all_labels().len());
+        for label in ModerationLabel::all_labels() {
+            if let Some(parent) = label.parent() {
+                assert_eq!(parent.category(), label.category());
+            }
+        }
+    }
+
+    #[test]
+    fn grouping_and_sorting_are_stable() {
+        let labels = [ModerationLabel::SP, ModerationLabel::SU, ModerationLabel::S];
+        assert_eq!(
+            LabelCategory::group(&labels),
+            vec![
+                (
+                    LabelCategory::Sexual,
+                    vec![ModerationLabel::S, ModerationLabel::SU]
+                ),
+                (LabelCategory::Spam, vec![ModerationLabel::SP]),
+            ]
+        );
+
+        let mut labels = labels.to_vec();
+        ModerationLabel::sort_by_severity(&mut labels);
+        assert_eq!(
+            labels,
+            vec![ModerationLabel::SU, ModerationLabel::S, ModerationLabel::SP]
+        );
+        assert!(ModerationLabel::SU.severity() > ModerationLabel::PM.severity());
+    }
+
+    #[test]
+    fn labels_parse_from_codes_and_long_names() {
+        for label in ModerationLabel::all_labels() {
+            assert_eq!(label.to_short_name().parse::<ModerationLabel>(), Ok(label));
+        }
+        assert_eq!(
+            "toxicity".parse::<ModerationLabel>(),
+            Ok(ModerationLabel::T)
+        );
+        assert_eq!(
+            "Self-Harm".parse::<ModerationLabel>(),
+            Ok(ModerationLabel::SH)
+        );
+        assert_eq!(
+            "sexual/minors".parse::<ModerationLabel>(),
+            Ok(ModerationLabel::SU)
+        );
+        assert_eq!("hr".parse::<ModerationLabel>(), Ok(ModerationLabel::HR));
+        assert_eq!("s2".parse::<ModerationLabel>(), Ok(ModerationLabel::S2));
+        assert_eq!("Sh".parse::<ModerationLabel>(), Ok(ModerationLabel::SH));
+
+        let err = "toxic".parse::<ModerationLabel>().unwrap_err();
+        assert_eq!(err.input, "toxic");
+        assert_eq!(err.type_name, "ModerationLabel");
+        assert!(err.expected.contains(&"toxicity"));
+        assert!(
+            err.to_string()
+                .starts_with("invalid ModerationLabel \"toxic\"")
+        );
+    }
+
+    fn video(bytes: usize) -> VideoModerationRequest {
+        VideoModerationRequest {
+            video: "A".repeat(bytes * 4 / 3),
+            model: None,
+            enabled_labels: None,
+            include_implicit: false,
+        }
+    }
+
+    #[test]
+    fn video_is_billed_per_moderated_frame_with_the_batch_discount() {
+        let frames = vec!["A".repeat(400); MAX_VIDEO_FRAMES + 5];
+        let estimate =
+            CostEstimate::for_video(&video(3_000), &frames, &ModerationModel::Observer).unwrap();
+        assert_eq!(estimate.items.len(), MAX_VIDEO_FRAMES);
+        assert_eq!(estimate.items[0].bytes, 300);
+        assert_eq!(estimate.items[0].credits, 75);
+        assert_eq!(estimate.subtotal, 75 * MAX_VIDEO_FRAMES as i64);
+        assert_eq!(estimate.batch_discount, estimate.subtotal / 2);
+
+        // One frame is a batch of one, so it is not discounted.
+        let estimate =
+            CostEstimate::for_video(&video(3_000), &frames[..1], &ModerationModel::Observer)
+                .unwrap();
+        assert_eq!(estimate.batch_discount, 0);
+        assert_eq!(estimate.total, 75);
+    }
+
+    #[test]
+    fn oversized_video_has_no_estimate() {
+        let too_big = video(MAX_VIDEO_BYTES as usize + 3);
+        assert_eq!(
+            CostEstimate::for_video(&too_big, &["AAAA"], &ModerationModel::Observer),
+            None
+        );
+    }
+
+    #[test]
+    fn auto_has_no_estimate_until_resolved() {
+        let model = ModerationModel::Auto;
+        assert_eq!(model.checked_credits_per_byte(), None);
+        assert_eq!(
+            CostEstimate::for_batch(&batch(&["hello"], &[]), &model),
+            None
+        );
+        assert_eq!(CostEstimate::for_video(&video(3), &["AAAA"], &model), None);
+
+        let resolved = ModerationModel::resolve_auto(100, 100, &ModerationModel::all_models());
+        assert!(CostEstimate::for_batch(&batch(&["hello"], &[]), &resolved).is_some());
+    }
+}
@@ -0,0 +1,536 @@
+//! Amounts of money as integer minor units.
+//!
+//! Stripe reports every amount as an integer count of the currency's smallest
+//! unit, so that is what [`Money`] stores. Only formatting ever divides, and it
+//! does so with integer arithmetic, so a price never picks up a stray
+//! fraction of a penny on its way to the page.
+//!
+//! The structs that carry money keep their existing wire format through the
+//! field modules at the bottom of this file, used as
+//! `#[serde(flatten, with = "...")]`.
+
+use crate::string_enum::open_string_enum;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt::Display;
+
+/// Zero-decimal currencies Stripe supports beyond the ones named below.
+const ZERO_DECIMAL: &[&str] = &[
+    "bif", "clp", "djf", "gnf", "kmf", "krw", "mga", "pyg", "rwf", "ugx", "vnd", "vuv", "xaf",
+    "xof", "xpf",
+];
+
+/// Three-decimal currencies Stripe supports.
+const THREE_DECIMAL: &[&str] = &["bhd", "jod", "kwd", "omr", "tnd"];
+
+open_string_enum! {
+    /// An ISO 4217 currency. Codes travel lowercase, as Stripe sends them.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub enum Currency {
+        /// Anything else Stripe might settle in, lowercased.
+        Other(String),
+        /// The currency credits are priced in.
+        Gbp = "gbp",
+        Usd = "usd",
+        Eur = "eur",
+        Jpy = "jpy",
+        Cad = "cad",
+        Aud = "aud",
+        Chf = "chf",
+        Cny = "cny",
+        Inr = "inr",
+    }
+    fn code;
+    normalize = str::to_lowercase;
+}
+
+impl Currency {
+    /// Digits after the decimal point: how many minor units make a major one,
+    /// as a power of ten.
+    pub fn exponent(&self) -> u32 {
+        match self {
+            Currency::Jpy => 0,
+            Currency::Other(code) if ZERO_DECIMAL.contains(&code.as_str()) => 0,
+            Currency::Other(code) if THREE_DECIMAL.contains(&code.as_str()) => 3,
+            _ => 2,
+        }
+    }
+
+    /// What goes in front of the number. Currencies without a symbol here
+    /// fall back to their code.
+    pub fn symbol(&self) -> &str {
+        match self {
+            Currency::Usd => "$",
+            Currency::Eur => "€",
+            Currency::Gbp => "£",
+            Currency::Jpy => "¥",
+            Currency::Cad => "CA$",
+            Currency::Aud => "A$",
+            Currency::Chf => "CHF ",
+            Currency::Cny => "¥",
+            Currency::Inr => "₹",
+            Currency::Other(code) => code,
+        }
+    }
+
+    fn minor_per_major(&self) -> i64 {
+        10i64.pow(self.exponent())
+    }
+
+    /// What goes in front of a formatted amount: the symbol, or for a
+    /// currency without one, its uppercase code and a space ("KRW 1299").
+    fn prefix(&self) -> Cow<'_, str> {
+        match self {
+            Currency::Other(code) => Cow::Owned(format!("{} ", code.to_uppercase())),
+            _ => Cow::Borrowed(self.symbol()),
+        }
+    }
+}
+
+/// An amount in a currency's minor units: pence, cents, or whole yen.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Money {
+    pub minor: i64,
+    pub currency: Currency,
+}
+
+impl Money {
+    pub fn new(minor: i64, currency: Currency) -> Self {
+        Self { minor, currency }
+    }
+
+    pub fn gbp(pence: i64) -> Self {
+        Self::new(pence, Currency::Gbp)
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        Self::new(0, currency)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.minor == 0
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.minor < 0
+    }
+
+    /// The sum, or `None` if the currencies differ or it overflows.
+    pub fn checked_add(&self, other: &Money) -> Option<Money> {
+        if self.currency != other.currency {
+            return None;
+        }
+        Some(Money::new(
+            self.minor.checked_add(other.minor)?,
+            self.currency.clone(),
+        ))
+    }
+
+    /// The difference, or `None` if the currencies differ or it overflows.
+    pub fn checked_sub(&self, other: &Money) -> Option<Money> {
+        if self.currency != other.currency {
+            return None;
+        }
+        Some(Money::new(
+            self.minor.checked_sub(other.minor)?,
+            self.currency.clone(),
+        ))
+    }
+
+    /// `numerator / denominator` of the amount, rounded to the nearest minor
+    /// unit with halves away from zero. For proration, where the fraction is a
+    /// share of a billing period. `denominator` must not be zero.
+    pub fn mul_ratio(&self, numerator: i64, denominator: i64) -> Money {
+        let product = i128::from(self.minor) * i128::from(numerator);
+        let denominator = i128::from(denominator);
+        let (quotient, rest) = (product / denominator, product % denominator);
+        let rounding = if rest.abs() * 2 >= denominator.abs() {
+            product.signum() * denominator.signum()
+        } else {
+            0
+        };
+        Money::new((quotient + rounding) as i64, self.currency.clone())
+    }
+
+    /// The amount in major units, for clients that still want a float. Never
+    /// do arithmetic on the result.
+    pub fn to_major_f64(&self) -> f64 {
+        self.minor as f64 / self.currency.minor_per_major() as f64
+    }
+
+    /// Like the `Display` form, but whole amounts of at least one major unit
+    /// drop their decimals: "£5" rather than "£5.00", while "£0.50" and
+    /// "£5.25" are unchanged.
+    pub fn format_compact(&self) -> String {
+        let per_major = self.currency.minor_per_major();
+        if self.minor.abs() >= per_major && self.minor % per_major == 0 {
+            let sign = if self.minor < 0 { "-" } else { "" };
+            format!(
+                "{}{}{}",
+                sign,
+                self.currency.prefix(),
+                self.minor.unsigned_abs() / per_major as u64
+            )
+        } else {
+            self.to_string()
+        }
+    }
+}
+
+impl Display for Money {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.minor < 0 { "-" } else { "" };
+        let exponent = self.currency.exponent();
+        let per_major = self.currency.minor_per_major() as u64;
+        let abs = self.minor.unsigned_abs();
+        if exponent == 0 {
+            write!(f, "{}{}{}", sign, self.currency.prefix(), abs)
+        } else {
+            write!(
+                f,
+                "{}{}{}.{:0width$}",
+                sign,
+                self.currency.prefix(),
+                abs / per_major,
+                abs % per_major,
+                width = exponent as usize
+            )
+        }
+    }
+}
+
+/// `amount` and `currency` fields, as on invoices and prices.
+pub mod amount_and_currency {
+    use super::{Currency, Money};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct Fields {
+        amount: i64,
+        currency: Currency,
+    }
+
+    pub fn serialize<S: Serializer>(money: &Money, serializer: S) -> Result<S::Ok, S::Error> {
+        Fields {
+            amount: money.minor,
+            currency: money.currency.clone(),
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Money, D::Error> {
+        let fields = Fields::deserialize(deserializer)?;
+        Ok(Money::new(fields.amount, fields.currency))
+    }
+}
+
+/// `price_cents` and `currency` fields, as on credit packs.
+pub mod price_cents_and_currency {
+    use super::{Currency, Money};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct Fields {
+        price_cents: i64,
+        currency: Currency,
+    }
+
+    pub fn serialize<S: Serializer>(money: &Money, serializer: S) -> Result<S::Ok, S::Error> {
+        Fields {
+            price_cents: money.minor,
+            currency: money.currency.clone(),
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Money, D::Error> {
+        let fields = Fields::deserialize(deserializer)?;
+        Ok(Money::new(fields.price_cents, fields.currency))
+    }
+}
+
+/// `amount_cents`, `amount_display` and `currency` fields, as on a
+/// [`crate::pricing::FormattedPrice`]. `amount_display` is written for older
+/// clients and ignored when reading, since it only restates `amount_cents`.
+pub mod formatted_amount {
+    use super::{Currency, Money};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize)]
+    struct Fields<'a> {
+        amount_cents: i64,
+        amount_display: f64,
+        currency: &'a Currency,
+    }
+
+    #[derive(Deserialize)]
+    struct Incoming {
+        amount_cents: i64,
+        currency: Currency,
+    }
+
+    pub fn serialize<S: Serializer>(money: &Money, serializer: S) -> Result<S::Ok, S::Error> {
+        Fields {
+            amount_cents: money.minor,
+            amount_display: money.to_major_f64(),
+            currency: &money.currency,
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Money, D::Error> {
+        let fields = Incoming::deserialize(deserializer)?;
+        Ok(Money::new(fields.amount_cents, fields.currency))
+    }
+}
+
+/// A price that may not be known, though its currency can be: Stripe
+/// subscription info has always been able to carry a currency without a
+/// price.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionalPrice {
+    Known(Money),
+    Unknown { currency: Option<Currency> },
+}
+
+impl OptionalPrice {
+    pub fn money(&self) -> Option<&Money> {
+        match self {
+            OptionalPrice::Known(money) => Some(money),
+            OptionalPrice::Unknown { .. } => None,
+        }
+    }
+
+    pub fn currency(&self) -> Option<&Currency> {
+        match self {
+            OptionalPrice::Known(money) => Some(&money.currency),
+            OptionalPrice::Unknown { currency } => currency.as_ref(),
+        }
+    }
+}
+
+impl Default for OptionalPrice {
+    fn default() -> Self {
+        OptionalPrice::Unknown { currency: None }
+    }
+}
+
+impl From<Money> for OptionalPrice {
+    fn from(money: Money) -> Self {
+        OptionalPrice::Known(money)
+    }
+}
+
+/// The optional `price` and `currency` fields Stripe subscription info has
+/// always carried, `price` being a float count of minor units. A price
+/// without a currency cannot be read, so it is unknown; a currency without a
+/// price is kept.
+pub mod optional_price_and_currency {
+    use super::{Currency, Money, OptionalPrice};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct Fields {
+        #[serde(default)]
+        price: Option<f64>,
+        #[serde(default)]
+        currency: Option<Currency>,
+    }
+
+    pub fn serialize<S: Serializer>(
+        price: &OptionalPrice,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        Fields {
+            price: price.money().map(|money| money.minor as f64),
+            currency: price.currency().cloned(),
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<OptionalPrice, D::Error> {
+        let fields = Fields::deserialize(deserializer)?;
+        Ok(match (fields.price, fields.currency) {
+            (Some(price), Some(currency)) => {
+                OptionalPrice::Known(Money::new(price.round() as i64, currency))
+            }
+            (_, currency) => OptionalPrice::Unknown { currency },
+        })
+    }
+}
+
+/// A `price` float in major units, with the `currency` field added alongside
+/// it; as on [`crate::pricing::SubscriptionInfo`], which used to send the bare
+/// float. As with [`optional_price_and_currency`], a price without a currency
+/// is unknown.
+pub mod major_price_and_currency {
+    use super::{Currency, Money, OptionalPrice};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct Fields {
+        #[serde(default)]
+        price: Option<f64>,
+        #[serde(default)]
+        currency: Option<Currency>,
+    }
+
+    pub fn serialize<S: Serializer>(
+        price: &OptionalPrice,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        Fields {
+            price: price.money().map(Money::to_major_f64),
+            currency: price.currency().cloned(),
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<OptionalPrice, D::Error> {
+        let fields = Fields::deserialize(deserializer)?;
+        Ok(match (fields.price, fields.currency) {
+            (Some(price), Some(currency)) => {
+                let per_major = 10f64.powi(currency.exponent() as i32);
+                OptionalPrice::Known(Money::new((price * per_major).round() as i64, currency))
+            }
+            (_, currency) => OptionalPrice::Unknown { currency },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_by_exponent() {
+        assert_eq!(Money::gbp(1299).to_string(), "£12.99");
+        assert_eq!(Money::gbp(5).to_string(), "£0.05");
+        assert_eq!(Money::gbp(-250).to_string(), "-£2.50");
+        assert_eq!(Money::new(1299, Currency::Jpy).to_string(), "¥1299");
+        assert_eq!(
+            Money::new(1299, Currency::from("KRW")).to_string(),
+            "KRW 1299"
+        );
+        assert_eq!(
+            Money::new(1299, Currency::from("bhd")).to_string(),
+            "BHD 1.299"
+        );
+        assert_eq!(
+            Money::new(1299, Currency::from("sek")).to_string(),
+            "SEK 12.99"
+        );
+        assert_eq!(Money::new(1000, Currency::Chf).to_string(), "CHF 10.00");
+    }
+
+    #[test]
+    fn compact_drops_decimals_only_for_whole_amounts() {
+        assert_eq!(Money::gbp(500).format_compact(), "£5");
+        assert_eq!(Money::gbp(525).format_compact(), "£5.25");
+        assert_eq!(Money::gbp(50).format_compact(), "£0.50");
+        assert_eq!(Money::gbp(0).format_compact(), "£0.00");
+        assert_eq!(Money::gbp(-300).format_compact(), "-£3");
+    }
+
+    #[test]
+    fn arithmetic_refuses_mixed_currencies() {
+        let a = Money::gbp(100);
+        assert_eq!(a.checked_add(&Money::gbp(50)), Some(Money::gbp(150)));
+        assert_eq!(a.checked_sub(&Money::gbp(150)), Some(Money::gbp(-50)));
+        assert_eq!(a.checked_add(&Money::new(50, Currency::Usd)), None);
+    }
+
+    #[test]
+    fn ratios_round_half_away_from_zero() {
+        assert_eq!(Money::gbp(1000).mul_ratio(1, 3), Money::gbp(333));
+        assert_eq!(Money::gbp(1000).mul_ratio(2, 3), Money::gbp(667));
+        assert_eq!(Money::gbp(5).mul_ratio(1, 2), Money::gbp(3));
+        assert_eq!(Money::gbp(-5).mul_ratio(1, 2), Money::gbp(-3));
+    }
+
+    #[test]
+    fn exponents() {
+        assert_eq!(Currency::Jpy.exponent(), 0);
+        assert_eq!(Currency::from("krw").exponent(), 0);
+        assert_eq!(Currency::from("KWD").exponent(), 3);
+        assert_eq!(Currency::Usd.exponent(), 2);
+        assert_eq!(Currency::from("sek").exponent(), 2);
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Subscription {
+        #[serde(flatten, with = "optional_price_and_currency")]
+        price: OptionalPrice,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct LegacySubscription {
+        price: Option<f64>,
+        currency: Option<String>,
+    }
+
+    #[test]
+    fn optional_price_keeps_the_stripe_wire_format() {
+        let parsed: Subscription =
+            serde_json::from_str(r#"{"price": 1299.0, "currency": "GBP"}"#).unwrap();
+        assert_eq!(parsed.price, OptionalPrice::Known(Money::gbp(1299)));
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            serde_json::json!({"price": 1299.0, "currency": "gbp"})
+        );
+
+        // Every shape the old `Option<f64>` and `Option<String>` pair allowed
+        // still parses, and reads back the same through the old struct.
+        for old in [
+            r#"{"price": 1299.0, "currency": "usd"}"#,
+            r#"{"price": null, "currency": "usd"}"#,
+            r#"{"price": null, "currency": null}"#,
+            r#"{}"#,
+        ] {
+            let parsed: Subscription = serde_json::from_str(old).unwrap();
+            let json = serde_json::to_string(&parsed).unwrap();
+            assert_eq!(
+                serde_json::from_str::<LegacySubscription>(&json).unwrap(),
+                serde_json::from_str::<LegacySubscription>(old).unwrap(),
+                "{}",
+                old
+            );
+        }
+
+        // A price without a currency is not guessed at.
+        let parsed: Subscription = serde_json::from_str(r#"{"price": 1299.0}"#).unwrap();
+        assert_eq!(parsed.price, OptionalPrice::Unknown { currency: None });
+        let parsed: Subscription =
+            serde_json::from_str(r#"{"price": null, "currency": "usd"}"#).unwrap();
+        assert_eq!(parsed.price.currency(), Some(&Currency::Usd));
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Plan {
+        #[serde(flatten, with = "major_price_and_currency")]
+        price: OptionalPrice,
+    }
+
+    #[test]
+    fn major_price_needs_a_currency() {
+        let parsed: Plan = serde_json::from_str(r#"{"price": 12.99, "currency": "gbp"}"#).unwrap();
+        assert_eq!(parsed.price, OptionalPrice::Known(Money::gbp(1299)));
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            serde_json::json!({"price": 12.99, "currency": "gbp"})
+        );
+        let parsed: Plan = serde_json::from_str(r#"{"price": 1299.0, "currency": "jpy"}"#).unwrap();
+        assert_eq!(
+            parsed.price,
+            OptionalPrice::Known(Money::new(1299, Currency::Jpy))
+        );
+
+        // The bare float older payloads carry has no currency to read it in.
+        let parsed: Plan = serde_json::from_str(r#"{"price": 12.99}"#).unwrap();
+        assert_eq!(parsed.price, OptionalPrice::Unknown { currency: None });
+    }
+}
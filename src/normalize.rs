@@ -0,0 +1,392 @@
+//! Reduce text to a canonical skeleton so filters see through obfuscation:
+//! zero-width characters, homoglyphs from other scripts, fullwidth and
+//! mathematical letters, leetspeak and s p a c e d letters.
+//!
+//! The skeleton keeps a map back to the original, so a match found in the
+//! skeleton is reported against the message the user actually sent. Which
+//! folds apply is chosen per caller with [`NormalizeOptions`]: leetspeak and
+//! letter joining suit words but would mangle a URL.
+
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NormalizeOptions {
+    /// Drop zero-width and other invisible characters, and combining marks.
+    pub strip_invisible: bool,
+    /// Map lookalikes to plain Latin: Cyrillic and Greek homoglyphs, accented
+    /// letters, fullwidth and mathematical alphanumerics.
+    pub fold_confusables: bool,
+    pub fold_case: bool,
+    /// `h3ll0` to `hello`, only inside words that contain a letter so plain
+    /// numbers survive.
+    pub fold_leetspeak: bool,
+    /// `n i c e` to `nice`: three or more single characters separated by
+    /// single spaces or punctuation are joined.
+    pub join_spaced_letters: bool,
+}
+
+impl NormalizeOptions {
+    /// Everything on. For word filters and usernames.
+    pub fn words() -> Self {
+        Self {
+            strip_invisible: true,
+            fold_confusables: true,
+            fold_case: true,
+            fold_leetspeak: true,
+            join_spaced_letters: true,
+        }
+    }
+
+    /// Only the folds that keep a URL a URL.
+    pub fn links() -> Self {
+        Self {
+            strip_invisible: true,
+            fold_confusables: true,
+            fold_case: true,
+            fold_leetspeak: false,
+            join_spaced_letters: false,
+        }
+    }
+}
+
+/// Normalized text plus, for every character of it, the byte range of the
+/// original character it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Skeleton {
+    pub text: String,
+    /// (byte offset in `text`, byte range in the original), one per char.
+    map: Vec<(usize, Range<usize>)>,
+}
+
+impl Skeleton {
+    /// The original byte range behind a byte range of the skeleton.
+    ///
+    /// Characters removed from inside the range (a zero-width joiner between
+    /// two letters, say) are covered, since the result runs from the first
+    /// original character to the last. An empty range maps to an empty range.
+    pub fn original_range(&self, range: Range<usize>) -> Range<usize> {
+        let first = self.map.iter().find(|(offset, _)| *offset >= range.start);
+        let last = self
+            .map
+            .iter()
+            .rev()
+            .find(|(offset, _)| *offset < range.end);
+        match (first, last) {
+            (Some((_, first)), Some((_, last))) if first.start < last.end => first.start..last.end,
+            (Some((_, first)), _) => first.start..first.start,
+            _ => {
+                let end = self.map.last().map_or(0, |(_, last)| last.end);
+                end..end
+            }
+        }
+    }
+}
+
+/// Normalize `text` according to `options`.
+pub fn skeleton(text: &str, options: &NormalizeOptions) -> Skeleton {
+    let mut chars: Vec<(char, Range<usize>)> = Vec::with_capacity(text.len());
+    for (start, c) in text.char_indices() {
+        let span = start..start + c.len_utf8();
+        if options.strip_invisible && is_invisible(c) {
+            continue;
+        }
+        let c = if options.fold_confusables {
+            fold_confusable(c)
+        } else {
+            c
+        };
+        if options.fold_case {
+            chars.extend(c.to_lowercase().map(|lower| (lower, span.clone())));
+        } else {
+            chars.push((c, span));
+        }
+    }
+
+    if options.fold_leetspeak {
+        fold_leetspeak(&mut chars);
+    }
+    if options.join_spaced_letters {
+        join_spaced_letters(&mut chars);
+    }
+
+    let mut skeleton = Skeleton {
+        text: String::with_capacity(chars.len()),
+        map: Vec::with_capacity(chars.len()),
+    };
+    for (c, span) in chars {
+        skeleton.map.push((skeleton.text.len(), span));
+        skeleton.text.push(c);
+    }
+    skeleton
+}
+
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00AD}'
+            | '\u{034F}'
+            | '\u{061C}'
+            | '\u{115F}'
+            | '\u{1160}'
+            | '\u{17B4}'
+            | '\u{17B5}'
+            | '\u{180E}'
+            | '\u{200B}'..='\u{200F}'
+            | '\u{202A}'..='\u{202E}'
+            | '\u{2060}'..='\u{2064}'
+            | '\u{2066}'..='\u{206F}'
+            | '\u{3164}'
+            | '\u{FE00}'..='\u{FE0F}'
+            | '\u{FEFF}'
+            | '\u{FFA0}'
+            // Combining diacritics, including the stacks of "zalgo" text.
+            | '\u{0300}'..='\u{036F}'
+            | '\u{1AB0}'..='\u{1AFF}'
+            | '\u{1DC0}'..='\u{1DFF}'
+            | '\u{20D0}'..='\u{20FF}'
+    )
+}
+
+fn fold_confusable(c: char) -> char {
+    let code = c as u32;
+    // Fullwidth ASCII.
+    if (0xFF01..=0xFF5E).contains(&code) {
+        return char::from_u32(code - 0xFEE0).unwrap_or(c);
+    }
+    // Mathematical alphanumeric letters: thirteen styles of A-Z then a-z.
+    if (0x1D400..=0x1D6A3).contains(&code) {
+        let i = (code - 0x1D400) % 52;
+        let base = if i < 26 { b'A' } else { b'a' - 26 };
+        return (base + i as u8) as char;
+    }
+    // Mathematical digits: five styles of 0-9.
+    if (0x1D7CE..=0x1D7FF).contains(&code) {
+        return (b'0' + ((code - 0x1D7CE) % 10) as u8) as char;
+    }
+    match c {
+        // Cyrillic.
+        'а' => 'a',
+        'в' => 'b',
+        'е' | 'ё' => 'e',
+        'з' => '3',
+        'і' | 'ї' => 'i',
+        'ј' => 'j',
+        'к' => 'k',
+        'м' => 'm',
+        'н' => 'h',
+        'о' => 'o',
+        'р' => 'p',
+        'с' => 'c',
+        'т' => 't',
+        'у' => 'y',
+        'х' => 'x',
+        'ѕ' => 's',
+        'ԁ' => 'd',
+        'ԛ' => 'q',
+        'ԝ' => 'w',
+        'А' => 'A',
+        'В' => 'B',
+        'Е' | 'Ё' => 'E',
+        'І' | 'Ї' => 'I',
+        'Ј' => 'J',
+        'К' => 'K',
+        'М' => 'M',
+        'Н' => 'H',
+        'О' => 'O',
+        'Р' => 'P',
+        'С' => 'C',
+        'Т' => 'T',
+        'У' => 'Y',
+        'Х' => 'X',
+        'Ѕ' => 'S',
+        // Greek.
+        'α' => 'a',
+        'β' => 'b',
+        'ε' => 'e',
+        'ι' => 'i',
+        'κ' => 'k',
+        'ν' => 'v',
+        'ο' => 'o',
+        'ρ' => 'p',
+        'τ' => 't',
+        'υ' => 'u',
+        'χ' => 'x',
+        'Α' => 'A',
+        'Β' => 'B',
+        'Ε' => 'E',
+        'Ζ' => 'Z',
+        'Η' => 'H',
+        'Ι' => 'I',
+        'Κ' => 'K',
+        'Μ' => 'M',
+        'Ν' => 'N',
+        'Ο' => 'O',
+        'Ρ' => 'P',
+        'Τ' => 'T',
+        'Υ' => 'Y',
+        'Χ' => 'X',
+        // Accented Latin.
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => 'a',
+        'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => 'c',
+        'ď' | 'đ' => 'd',
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => 'e',
+        'ĝ' | 'ğ' | 'ġ' | 'ģ' => 'g',
+        'ĥ' | 'ħ' => 'h',
+        'ì' | 'í' | 'î' | 'ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => 'i',
+        'ĵ' => 'j',
+        'ķ' => 'k',
+        'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => 'l',
+        'ñ' | 'ń' | 'ņ' | 'ň' => 'n',
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => 'o',
+        'ŕ' | 'ŗ' | 'ř' => 'r',
+        'ś' | 'ŝ' | 'ş' | 'š' => 's',
+        'ţ' | 'ť' | 'ŧ' => 't',
+        'ù' | 'ú' | 'û' | 'ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => 'u',
+        'ŵ' => 'w',
+        'ý' | 'ÿ' | 'ŷ' => 'y',
+        'ź' | 'ż' | 'ž' => 'z',
+        'À' | 'Á' | 'Â' | 'Ã' | 'Ä' | 'Å' | 'Ā' | 'Ă' | 'Ą' => 'A',
+        'Ç' | 'Ć' | 'Ĉ' | 'Ċ' | 'Č' => 'C',
+        'È' | 'É' | 'Ê' | 'Ë' | 'Ē' | 'Ĕ' | 'Ė' | 'Ę' | 'Ě' => 'E',
+        'Ì' | 'Í' | 'Î' | 'Ï' | 'Ĩ' | 'Ī' | 'Ĭ' | 'Į' | 'İ' => 'I',
+        'Ñ' | 'Ń' | 'Ņ' | 'Ň' => 'N',
+        'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ö' | 'Ø' | 'Ō' | 'Ŏ' | 'Ő' => 'O',
+        'Ù' | 'Ú' | 'Û' | 'Ü' | 'Ũ' | 'Ū' | 'Ŭ' | 'Ů' | 'Ű' | 'Ų' => 'U',
+        'Ý' | 'Ÿ' | 'Ŷ' => 'Y',
+        _ => c,
+    }
+}
+
+fn leet(c: char) -> Option<char> {
+    match c {
+        '0' => Some('o'),
+        '1' => Some('i'),
+        '3' => Some('e'),
+        '4' => Some('a'),
+        '5' => Some('s'),
+        '7' => Some('t'),
+        '8' => Some('b'),
+        '@' => Some('a'),
+        '$' => Some('s'),
+        _ => None,
+    }
+}
+
+/// Words are runs of non-whitespace. Digits fold in any word that has a
+/// letter; `@` and `$` only between two word characters, so `@everyone` and
+/// `$5` keep their meaning.
+fn fold_leetspeak(chars: &mut [(char, Range<usize>)]) {
+    let mut start = 0;
+    while start < chars.len() {
+        let end = chars[start..]
+            .iter()
+            .position(|(c, _)| c.is_whitespace())
+            .map_or(chars.len(), |i| start + i);
+        if chars[start..end].iter().any(|(c, _)| c.is_alphabetic()) {
+            for i in start..end {
+                let c = chars[i].0;
+                let Some(folded) = leet(c) else { continue };
+                let enclosed = i > start
+                    && i + 1 < end
+                    && (chars[i - 1].0.is_alphanumeric() || leet(chars[i - 1].0).is_some())
+                    && (chars[i + 1].0.is_alphanumeric() || leet(chars[i + 1].0).is_some());
+                if c.is_ascii_digit() || enclosed {
+                    chars[i].0 = folded;
+                }
+            }
+        }
+        start = end + 1;
+    }
+}
+
+fn is_spacer(c: char) -> bool {
+    c == ' ' || matches!(c, '.' | '-' | '_' | '*' | '~' | '|' | '/' | '\\')
+}
+
+fn join_spaced_letters(chars: &mut Vec<(char, Range<usize>)>) {
+    let is_single = |chars: &[(char, Range<usize>)], i: usize| {
+        chars[i].0.is_alphanumeric()
+            && (i == 0 || !chars[i - 1].0.is_alphanumeric())
+            && chars.get(i + 1).is_none_or(|(c, _)| !c.is_alphanumeric())
+    };
+
+    let mut drop = vec![false; chars.len()];
+    let mut i = 0;
+    while i < chars.len() {
+        if !is_single(chars, i) {
+            i += 1;
+            continue;
+        }
+        // Extend while the pattern "single, one spacer, single" holds.
+        let mut letters = vec![i];
+        let mut j = i;
+        while j + 2 < chars.len() && is_spacer(chars[j + 1].0) && is_single(chars, j + 2) {
+            j += 2;
+            letters.push(j);
+        }
+        if letters.len() >= 3 {
+            for pair in letters.windows(2) {
+                drop[pair[0] + 1] = true;
+            }
+        }
+        i = j + 1;
+    }
+
+    let mut index = 0;
+    chars.retain(|_| {
+        let keep = !drop[index];
+        index += 1;
+        keep
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(text: &str) -> String {
+        skeleton(text, &NormalizeOptions::words()).text
+    }
+
+    #[test]
+    fn folds() {
+        // Zero-width joiners and a soft hyphen.
+        assert_eq!(words("ba\u{200D}d wo\u{00AD}rd"), "bad word");
+        // Cyrillic а, е and о.
+        assert_eq!(words("b\u{0430}d h\u{0435}ll\u{043E}"), "bad hello");
+        assert_eq!(words("ＢＡＤ 𝐛𝐚𝐝"), "bad bad");
+        assert_eq!(words("h3ll0 w0rld"), "hello world");
+        assert_eq!(words("n i c e and n.i.c.e"), "nice and nice");
+        assert_eq!(words("Café"), "cafe");
+        assert_eq!(words("zalgo\u{0301}\u{0316}"), "zalgo");
+    }
+
+    #[test]
+    fn leaves_ordinary_text_alone() {
+        assert_eq!(words("I am a cat"), "i am a cat");
+        assert_eq!(
+            words("pay $50 to @everyone by 2024"),
+            "pay $50 to @everyone by 2024"
+        );
+        assert_eq!(words("p@$$word"), "password");
+    }
+
+    #[test]
+    fn links_keep_their_shape() {
+        let options = NormalizeOptions::links();
+        assert_eq!(
+            skeleton("ｄｉｓｃｏｒｄ.gg/ab1", &options).text,
+            "discord.gg/ab1"
+        );
+    }
+
+    #[test]
+    fn maps_back_to_the_original() {
+        let text = "say n i\u{200B} c e!";
+        let skeleton = skeleton(text, &NormalizeOptions::words());
+        assert_eq!(skeleton.text, "say nice!");
+        let original = skeleton.original_range(4..8);
+        assert_eq!(&text[original], "n i\u{200B} c e");
+    }
+}
@@ -1,3 +1,4 @@
+use crate::budget::BudgetAlertSettings;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -28,9 +29,14 @@ fn default_notification_type() -> String {
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct NotificationPreferencesResponse {
     pub marketing_notifications_enabled: bool,
+    #[serde(default)]
+    pub budget_alerts: BudgetAlertSettings,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct UpdateNotificationPreferencesRequest {
     pub marketing_notifications_enabled: bool,
-}
\ No newline at end of file
+    /// Left as they are when omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub budget_alerts: Option<BudgetAlertSettings>,
+}
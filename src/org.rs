@@ -0,0 +1,445 @@
+//! Organizations: several people sharing one subscription, one credit pool
+//! and one set of API keys.
+//!
+//! What each role may do is decided by [`permits`] alone, so the dashboard
+//! hides exactly the buttons the backend would refuse.
+
+use crate::error::ParseEnumError;
+use crate::pricing::{CreditBalance, StripeSubscriptionInfo};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// How long an invitation can be accepted for.
+pub const INVITATION_TTL_DAYS: i64 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrgRole {
+    /// Everything, including members and deleting the organization.
+    Owner,
+    /// The subscription, invoices and credit purchases.
+    Billing,
+    /// API keys and Discord guild settings.
+    Developer,
+    /// Usage and settings, read-only.
+    Viewer,
+}
+
+impl FromStr for OrgRole {
+    type Err = ParseEnumError;
+
+    fn from_str(input: &str) -> Result<OrgRole, Self::Err> {
+        match input {
+            "owner" => Ok(OrgRole::Owner),
+            "billing" => Ok(OrgRole::Billing),
+            "developer" => Ok(OrgRole::Developer),
+            "viewer" => Ok(OrgRole::Viewer),
+            _ => Err(ParseEnumError::new(
+                input,
+                "OrgRole",
+                &["owner", "billing", "developer", "viewer"],
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for OrgRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OrgRole::Owner => write!(f, "owner"),
+            OrgRole::Billing => write!(f, "billing"),
+            OrgRole::Developer => write!(f, "developer"),
+            OrgRole::Viewer => write!(f, "viewer"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrgPermission {
+    /// Credit balance, usage history and settings.
+    ViewUsage,
+    /// Change plan, buy credits, overdraft and budget settings, invoices.
+    ManageBilling,
+    /// Create and revoke the organization's API keys.
+    ManageApiKeys,
+    /// Configure the Discord guilds billed to the organization.
+    ManageGuilds,
+    /// Invite, remove and change the role of members.
+    ManageMembers,
+    DeleteOrganization,
+}
+
+impl std::fmt::Display for OrgPermission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OrgPermission::ViewUsage => write!(f, "viewing usage"),
+            OrgPermission::ManageBilling => write!(f, "managing billing"),
+            OrgPermission::ManageApiKeys => write!(f, "managing API keys"),
+            OrgPermission::ManageGuilds => write!(f, "managing Discord servers"),
+            OrgPermission::ManageMembers => write!(f, "managing members"),
+            OrgPermission::DeleteOrganization => write!(f, "deleting the organization"),
+        }
+    }
+}
+
+/// Whether `role` grants `permission`.
+pub fn permits(role: OrgRole, permission: OrgPermission) -> bool {
+    use OrgPermission::*;
+    match role {
+        OrgRole::Owner => true,
+        OrgRole::Billing => matches!(permission, ViewUsage | ManageBilling),
+        OrgRole::Developer => matches!(permission, ViewUsage | ManageApiKeys | ManageGuilds),
+        OrgRole::Viewer => matches!(permission, ViewUsage),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrgError {
+    /// The acting member's role does not grant this.
+    Forbidden {
+        permission: OrgPermission,
+    },
+    NotAMember {
+        user_id: String,
+    },
+    /// The change would leave the organization without an owner.
+    LastOwner,
+    InvitationExpired,
+    /// The invitation was sent to another address than the accepting
+    /// account's.
+    InvitationEmailMismatch,
+}
+
+impl std::fmt::Display for OrgError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OrgError::Forbidden { permission } => {
+                write!(f, "your role does not allow {}", permission)
+            }
+            OrgError::NotAMember { user_id } => {
+                write!(f, "{} is not a member of this organization", user_id)
+            }
+            OrgError::LastOwner => write!(f, "an organization needs at least one owner"),
+            OrgError::InvitationExpired => write!(f, "this invitation has expired"),
+            OrgError::InvitationEmailMismatch => {
+                write!(f, "this invitation was sent to a different email address")
+            }
+        }
+    }
+}
+
+impl std::error::Error for OrgError {}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Organization {
+    pub id: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    /// Email domains the organization has proven it owns. Logins through its
+    /// own identity provider may only match existing accounts in these; see
+    /// [`crate::auth::ExternalProfile::matching_email`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub verified_domains: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrgMember {
+    pub user_id: String,
+    pub email: String,
+    pub role: OrgRole,
+    pub joined_at: DateTime<Utc>,
+}
+
+/// An organization as its members see it. Credits are the organization's, not
+/// any member's: usage through its API keys or its guilds draws from here.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrganizationResponse {
+    pub organization: Organization,
+    pub members: Vec<OrgMember>,
+    pub credits: CreditBalance,
+    pub subscription: StripeSubscriptionInfo,
+    /// The requesting member's role, for deciding what to show.
+    pub your_role: OrgRole,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateOrganizationRequest {
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateMemberRoleRequest {
+    pub role: OrgRole,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateOrgInvitationRequest {
+    pub email: String,
+    pub role: OrgRole,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrgInvitation {
+    pub id: String,
+    pub org_id: String,
+    pub org_name: String,
+    pub email: String,
+    pub role: OrgRole,
+    pub invited_by: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl OrgInvitation {
+    pub fn new(
+        org: &Organization,
+        email: String,
+        role: OrgRole,
+        invited_by: String,
+        now: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            org_id: org.id.clone(),
+            org_name: org.name.clone(),
+            email,
+            role,
+            invited_by,
+            created_at: now,
+            expires_at: now + Duration::days(INVITATION_TTL_DAYS),
+        }
+    }
+
+    /// The membership accepting this creates. `email` must be the accepting
+    /// account's verified address, and the one the invitation was sent to:
+    /// the invitation id alone is not proof of being invited.
+    pub fn accept(
+        &self,
+        user_id: String,
+        email: String,
+        now: DateTime<Utc>,
+    ) -> Result<OrgMember, OrgError> {
+        if now >= self.expires_at {
+            return Err(OrgError::InvitationExpired);
+        }
+        if !email.trim().eq_ignore_ascii_case(self.email.trim()) {
+            return Err(OrgError::InvitationEmailMismatch);
+        }
+        Ok(OrgMember {
+            user_id,
+            email,
+            role: self.role,
+            joined_at: now,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AcceptOrgInvitationRequest {
+    pub invitation_id: String,
+}
+
+fn role_of(members: &[OrgMember], user_id: &str) -> Result<OrgRole, OrgError> {
+    members
+        .iter()
+        .find(|member| member.user_id == user_id)
+        .map(|member| member.role)
+        .ok_or_else(|| OrgError::NotAMember {
+            user_id: user_id.to_string(),
+        })
+}
+
+/// Check that `actor_id` may do something needing `permission`.
+pub fn authorize(
+    members: &[OrgMember],
+    actor_id: &str,
+    permission: OrgPermission,
+) -> Result<OrgRole, OrgError> {
+    let role = role_of(members, actor_id)?;
+    if !permits(role, permission) {
+        return Err(OrgError::Forbidden { permission });
+    }
+    Ok(role)
+}
+
+/// Check that `actor_id` may give `target_id` the role `new_role`, or remove
+/// them when `new_role` is `None`. Members may always leave themselves, but
+/// the last owner can neither leave nor be demoted.
+pub fn check_membership_change(
+    members: &[OrgMember],
+    actor_id: &str,
+    target_id: &str,
+    new_role: Option<OrgRole>,
+) -> Result<(), OrgError> {
+    let leaving = actor_id == target_id && new_role.is_none();
+    if !leaving {
+        authorize(members, actor_id, OrgPermission::ManageMembers)?;
+    }
+    let current = role_of(members, target_id)?;
+    let owners = members
+        .iter()
+        .filter(|member| member.role == OrgRole::Owner)
+        .count();
+    if current == OrgRole::Owner && new_role != Some(OrgRole::Owner) && owners == 1 {
+        return Err(OrgError::LastOwner);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_760_000_000, 0).unwrap()
+    }
+
+    fn member(user_id: &str, role: OrgRole) -> OrgMember {
+        OrgMember {
+            user_id: user_id.to_string(),
+            email: format!("{}@example.com", user_id),
+            role,
+            joined_at: now(),
+        }
+    }
+
+    fn team() -> Vec<OrgMember> {
+        vec![
+            member("alex", OrgRole::Owner),
+            member("sam", OrgRole::Billing),
+            member("kim", OrgRole::Developer),
+            member("lee", OrgRole::Viewer),
+        ]
+    }
+
+    #[test]
+    fn roles_grant_their_permissions_only() {
+        use OrgPermission::*;
+        let all = [
+            ViewUsage,
+            ManageBilling,
+            ManageApiKeys,
+            ManageGuilds,
+            ManageMembers,
+            DeleteOrganization,
+        ];
+        let granted = |role| {
+            all.iter()
+                .copied()
+                .filter(|permission| permits(role, *permission))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(granted(OrgRole::Owner), all.to_vec());
+        assert_eq!(granted(OrgRole::Billing), vec![ViewUsage, ManageBilling]);
+        assert_eq!(
+            granted(OrgRole::Developer),
+            vec![ViewUsage, ManageApiKeys, ManageGuilds]
+        );
+        assert_eq!(granted(OrgRole::Viewer), vec![ViewUsage]);
+    }
+
+    #[test]
+    fn authorize_checks_membership_then_role() {
+        let members = team();
+        assert_eq!(
+            authorize(&members, "kim", OrgPermission::ManageApiKeys),
+            Ok(OrgRole::Developer)
+        );
+        assert_eq!(
+            authorize(&members, "kim", OrgPermission::ManageBilling),
+            Err(OrgError::Forbidden {
+                permission: OrgPermission::ManageBilling
+            })
+        );
+        assert!(matches!(
+            authorize(&members, "stranger", OrgPermission::ViewUsage),
+            Err(OrgError::NotAMember { .. })
+        ));
+    }
+
+    #[test]
+    fn the_last_owner_stays() {
+        let members = team();
+        assert_eq!(
+            check_membership_change(&members, "alex", "alex", None),
+            Err(OrgError::LastOwner)
+        );
+        assert_eq!(
+            check_membership_change(&members, "alex", "alex", Some(OrgRole::Viewer)),
+            Err(OrgError::LastOwner)
+        );
+        // Anyone may leave; only owners may remove others.
+        assert_eq!(
+            check_membership_change(&members, "lee", "lee", None),
+            Ok(())
+        );
+        assert!(check_membership_change(&members, "sam", "lee", None).is_err());
+
+        let mut members = members;
+        members.push(member("jo", OrgRole::Owner));
+        assert_eq!(
+            check_membership_change(&members, "jo", "alex", None),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn invitations_expire() {
+        let org = Organization {
+            id: "org_1".to_string(),
+            name: "Mods".to_string(),
+            created_at: now(),
+            verified_domains: Vec::new(),
+        };
+        let invitation = OrgInvitation::new(
+            &org,
+            "new@example.com".to_string(),
+            OrgRole::Developer,
+            "alex".to_string(),
+            now(),
+        );
+        let member = invitation
+            .accept("new".to_string(), "New@Example.com".to_string(), now())
+            .unwrap();
+        assert_eq!(member.role, OrgRole::Developer);
+        assert_eq!(member.email, "New@Example.com");
+        assert_eq!(
+            invitation.accept(
+                "new".to_string(),
+                "new@example.com".to_string(),
+                now() + Duration::days(INVITATION_TTL_DAYS)
+            ),
+            Err(OrgError::InvitationExpired)
+        );
+    }
+
+    #[test]
+    fn invitations_only_admit_the_invitee() {
+        let org = Organization {
+            id: "org_1".to_string(),
+            name: "Mods".to_string(),
+            created_at: now(),
+            verified_domains: Vec::new(),
+        };
+        let invitation = OrgInvitation::new(
+            &org,
+            "new@example.com".to_string(),
+            OrgRole::Owner,
+            "alex".to_string(),
+            now(),
+        );
+        assert_eq!(
+            invitation.accept("eve".to_string(), "eve@example.com".to_string(), now()),
+            Err(OrgError::InvitationEmailMismatch)
+        );
+        assert_eq!(
+            OrgError::Forbidden {
+                permission: OrgPermission::ManageApiKeys
+            }
+            .to_string(),
+            "your role does not allow managing API keys"
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 use crate::pricing::{BillingCycle, Tier};
 
@@ -115,6 +116,9 @@ pub struct PlatformUserCreditsResponse {
     pub remaining_this_month: i64,
     pub extra_credits: i64,
     pub reset_date: Option<String>,
+    /// What makes up `extra_credits`, soonest to expire first.
+    #[serde(default)]
+    pub buckets: Vec<crate::credits::CreditBucket>,
 }
 
 // Plan change (existing subscription, same Stripe customer)
@@ -126,6 +130,15 @@ pub struct PlatformChangePlanRequest {
     pub billing_cycle: BillingCycle,
 }
 
+/// Preview of a [`PlatformChangePlanRequest`]; answered with a
+/// [`crate::pricing::ChangePlanPreviewResponse`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlatformChangePlanPreviewRequest {
+    pub user_email: String,
+    pub tier: Tier,
+    pub billing_cycle: BillingCycle,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PlatformChangePlanResponse {
     pub subscription_id: String,
@@ -171,6 +184,9 @@ pub struct PlatformModerationRequest {
     pub include_context: bool,
     #[serde(default)]
     pub include_implicit: bool,
+    /// Per-label score thresholds, as on [`crate::moderate::ModerationRequest`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thresholds: Option<HashMap<crate::moderate::ModerationLabel, f32>>,
 }
 
 // Consent / Authorization
@@ -1,7 +1,11 @@
+use crate::error::ParseEnumError;
+use crate::money::{Currency, Money, OptionalPrice};
+use crate::string_enum::open_string_enum;
 use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt::Display;
+use std::str::FromStr;
 
 /// Length of the free trial, in days.
 ///
@@ -19,21 +23,43 @@ pub const TRIAL_PERIOD_DAYS: u32 = 7;
 /// Credits per 1 GBP. Based on £2.00 = 1,500,000 credits.
 pub const CREDITS_PER_GBP: f64 = 750_000.0;
 
-pub fn credits_to_gbp(credits: i64) -> f64 {
-    credits as f64 / CREDITS_PER_GBP
+/// Credits per penny: [`CREDITS_PER_GBP`] for integer arithmetic.
+pub const CREDITS_PER_PENNY: i64 = 7_500;
+
+/// Credits are worth less than a penny each, so this rounds to the nearest
+/// penny, halves away from zero.
+pub fn credits_to_gbp(credits: i64) -> Money {
+    let per_penny = CREDITS_PER_PENNY;
+    let (pence, rest) = (credits / per_penny, credits % per_penny);
+    let rounding = if rest.abs() * 2 >= per_penny {
+        credits.signum()
+    } else {
+        0
+    };
+    Money::new(pence + rounding, Currency::Gbp)
 }
 
 pub fn format_credits_as_gbp(credits: i64) -> String {
-    let gbp = credits_to_gbp(credits);
-    let rounded_pence = (gbp * 100.0).round();
-    if rounded_pence == rounded_pence.floor() && gbp >= 1.0 && (rounded_pence % 100.0) == 0.0 {
-        format!("£{:.0}", gbp)
-    } else {
-        format!("£{:.2}", gbp)
+    credits_to_gbp(credits).format_compact()
+}
+
+/// A credit count with thousands separators, e.g. "1,500,000".
+pub fn format_credits(credits: i64) -> String {
+    let digits = credits.unsigned_abs().to_string();
+    let mut formatted = String::new();
+    if credits < 0 {
+        formatted.push('-');
     }
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreditBalance {
     pub used_current_period: i64,
     pub max_monthly_credits: i64,
@@ -43,27 +69,208 @@ pub struct CreditBalance {
     pub extra_credits: i64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+open_string_enum! {
+    /// What moved credits. Amounts are signed: positive adds to the balance.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub enum CreditTransactionKind {
+        /// A kind added after this was written, kept verbatim.
+        Unknown(String),
+        /// Credits spent on moderation. Negative.
+        Usage = "usage",
+        /// The start of a billing period's allowance. Unused allowance from the
+        /// period before does not carry over, so the amount is whatever brings the
+        /// allowance back to full.
+        MonthlyGrant = "monthly_grant",
+        /// A credit pack bought. Positive, into extra credits.
+        PackPurchase = "pack_purchase",
+        /// Paid out when a referred user subscribes. Positive, into extra credits.
+        ReferralReward = "referral_reward",
+        /// Credits returned for a request that failed. Positive, and taken off
+        /// the period's usage.
+        Refund = "refund",
+        /// A manual correction by support. Either sign.
+        AdminAdjustment = "admin_adjustment",
+        /// Usage charged to the overdraft once the balance is exhausted. Negative.
+        Overdraft = "overdraft",
+    }
+    fn as_str;
+}
+
+impl CreditTransactionKind {
+    /// Whether the amount counts towards `used_current_period`. Refunds count
+    /// too, negatively.
+    pub fn counts_as_usage(&self) -> bool {
+        matches!(
+            self,
+            CreditTransactionKind::Usage
+                | CreditTransactionKind::Overdraft
+                | CreditTransactionKind::Refund
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreditTransaction {
     pub id: String,
     pub amount: i64,
-    pub transaction_type: String,
+    pub transaction_type: CreditTransactionKind,
+    /// Total balance, allowance plus extra credits, right after this entry.
+    /// Missing on entries written before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balance_after: Option<i64>,
+    /// The API key behind a usage entry. `None` for usage from the dashboard
+    /// or the Discord bot, and for every other kind.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_id: Option<String>,
     pub model_type: Option<String>,
     pub bytes_processed: Option<i64>,
     pub description: String,
     pub created_at: NaiveDateTime,
 }
 
+/// Days after the first failed payment on which Stripe retries it. After the
+/// last one fails the subscription stops granting service.
+///
+/// Stripe does not report its schedule, so this is a copy of the custom retry
+/// schedule in the Stripe dashboard's failed-payment settings and must be
+/// changed together with it. Pass it to [`DunningSummary::new`] unless the
+/// schedule comes from configuration.
+pub const PAYMENT_RETRY_DAYS: [i64; 3] = [3, 8, 15];
+
+open_string_enum! {
+    /// Stripe's subscription states, plus `inactive` for a customer with no
+    /// subscription at all.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub enum SubscriptionStatus {
+        /// A state Stripe added after this was written, kept verbatim.
+        Unknown(String),
+        Trialing = "trialing",
+        Active = "active",
+        PastDue = "past_due",
+        Unpaid = "unpaid",
+        Incomplete = "incomplete",
+        IncompleteExpired = "incomplete_expired",
+        Canceled = "canceled",
+        Paused = "paused",
+        Inactive = "inactive",
+    }
+    fn as_str;
+}
+
+impl SubscriptionStatus {
+    /// Whether the customer gets what they pay for. `PastDue` still does:
+    /// service continues while Stripe retries the payment, and only stops
+    /// once the retries run out.
+    pub fn grants_service(&self) -> bool {
+        matches!(
+            self,
+            SubscriptionStatus::Trialing | SubscriptionStatus::Active | SubscriptionStatus::PastDue
+        )
+    }
+
+    /// Whether the customer has to fix their payment method. Unknown states
+    /// are never treated as needing action, so a new Stripe state cannot
+    /// start nagging customers before someone has looked at it.
+    pub fn needs_payment_action(&self) -> bool {
+        matches!(
+            self,
+            SubscriptionStatus::PastDue
+                | SubscriptionStatus::Unpaid
+                | SubscriptionStatus::Incomplete
+        )
+    }
+}
+
+open_string_enum! {
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub enum InvoiceStatus {
+        /// A state Stripe added after this was written, kept verbatim.
+        Unknown(String),
+        Draft = "draft",
+        Open = "open",
+        Paid = "paid",
+        Uncollectible = "uncollectible",
+        Void = "void",
+    }
+    fn as_str;
+}
+
+impl InvoiceStatus {
+    /// Finalized but not paid yet: the invoice's hosted page can still take
+    /// the payment.
+    pub fn needs_payment_action(&self) -> bool {
+        matches!(self, InvoiceStatus::Open)
+    }
+
+    /// Nothing more will happen to this invoice.
+    pub fn is_settled(&self) -> bool {
+        matches!(
+            self,
+            InvoiceStatus::Paid | InvoiceStatus::Uncollectible | InvoiceStatus::Void
+        )
+    }
+}
+
+/// Where a customer with a failed payment stands.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DunningSummary {
+    pub status: SubscriptionStatus,
+    /// Payment attempts made so far, the original charge included.
+    pub attempts_made: u32,
+    pub attempts_remaining: u32,
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    /// When service stops if every remaining attempt fails, or `None` if it
+    /// already has.
+    pub service_ends_at: Option<DateTime<Utc>>,
+}
+
+impl DunningSummary {
+    /// The summary for a subscription whose payment first failed at
+    /// `first_failed_at` and has been tried `attempts_made` times, as Stripe's
+    /// `attempt_count` on the invoice counts them. `retry_days` is Stripe's
+    /// retry schedule, usually [`PAYMENT_RETRY_DAYS`]. `None` when `status`
+    /// needs no payment action, so there is nothing to show.
+    pub fn new(
+        status: SubscriptionStatus,
+        first_failed_at: DateTime<Utc>,
+        attempts_made: u32,
+        retry_days: &[i64],
+    ) -> Option<Self> {
+        if !status.needs_payment_action() {
+            return None;
+        }
+        // Once service has stopped Stripe has given up retrying.
+        let retries_done = attempts_made.saturating_sub(1) as usize;
+        let retry_at = |day: &i64| first_failed_at + chrono::Duration::days(*day);
+        let (attempts_remaining, next_attempt_at, service_ends_at) = if status.grants_service() {
+            (
+                retry_days.len().saturating_sub(retries_done) as u32,
+                retry_days.get(retries_done).map(retry_at),
+                retry_days.last().map(retry_at),
+            )
+        } else {
+            (0, None, None)
+        };
+        Some(Self {
+            status,
+            attempts_made,
+            attempts_remaining,
+            next_attempt_at,
+            service_ends_at,
+        })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StripeSubscriptionInfo {
     pub tier: Tier,
     pub billing_cycle: BillingCycle,
-    pub status: String,
+    pub status: SubscriptionStatus,
     pub current_period_end: Option<DateTime<Utc>>,
     pub cancel_at_period_end: bool,
     pub is_active: bool,
-    pub price: Option<f64>, // Price in the smallest currency unit (e.g., pence, cents)
-    pub currency: Option<String>, // Currency code (e.g., "gbp", "usd")
+    #[serde(flatten, with = "crate::money::optional_price_and_currency")]
+    pub price: OptionalPrice,
     pub payment_method_id: Option<String>, // Default payment method ID
     #[serde(default)]
     pub subscription_id: Option<String>,
@@ -81,6 +288,9 @@ pub struct StripeSubscriptionInfo {
     /// product or is granted to a single customer.
     #[serde(default)]
     pub entitlements: Vec<crate::entitlements::Entitlement>,
+    /// Set while a payment is failing, for the dashboard's warning banner.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dunning: Option<DunningSummary>,
 }
 
 impl StripeSubscriptionInfo {
@@ -88,20 +298,111 @@ impl StripeSubscriptionInfo {
         Self {
             tier: Tier::Free,
             billing_cycle: BillingCycle::Monthly,
-            status: "inactive".to_string(),
+            status: SubscriptionStatus::Inactive,
             current_period_end: None,
             cancel_at_period_end: false,
             is_active: false,
-            price: None,
-            currency: None,
+            price: OptionalPrice::default(),
             payment_method_id: None,
             subscription_id: None,
             overdraft_enabled: false,
             overdraft_limit: None,
             overdraft_used: None,
             entitlements: Vec::new(),
+            dunning: None,
         }
     }
+
+    /// What moving to `target_cycle` of `target` would cost, without moving.
+    ///
+    /// A change is a downgrade if it lowers the tier or shortens the
+    /// commitment as [`BillingCycle::months`] orders them. Downgrades wait for
+    /// the paid period to end and charge nothing now. An upgrade takes effect
+    /// immediately and credits the unused share of the current period, by
+    /// time: on the same cycle only the difference for the rest of the period
+    /// is charged, while a new cycle restarts the period and bills it in full.
+    /// The current price is what Stripe says the customer pays, falling back
+    /// to `current`'s list price when Stripe has none.
+    pub fn preview_change(
+        &self,
+        current: &TierPricing,
+        target: &TierPricing,
+        target_cycle: BillingCycle,
+        now: DateTime<Utc>,
+    ) -> Result<ChangePlanPreviewResponse, PlanChangeError> {
+        if self.is_active && self.tier == target.tier && self.billing_cycle == target_cycle {
+            return Err(PlanChangeError::SamePlan);
+        }
+        let new_period_price = target
+            .prices
+            .get(&target_cycle)
+            .map(|price| price.amount.clone())
+            .ok_or(PlanChangeError::CycleNotOffered {
+                tier: target.tier,
+                billing_cycle: target_cycle,
+            })?;
+        let monthly_credits_change =
+            target.monthly_credits.unwrap_or(0) - current.monthly_credits.unwrap_or(0);
+
+        let rank = |tier: Tier| Tier::all_tiers().iter().position(|t| *t == tier);
+        let is_downgrade = self.is_active
+            && (rank(target.tier) < rank(self.tier)
+                || self.billing_cycle.is_downgrade_to(target_cycle));
+
+        let zero = Money::zero(new_period_price.currency.clone());
+        let period = self
+            .current_period_end
+            .filter(|_| self.is_active)
+            .and_then(|end| {
+                let start =
+                    end.checked_sub_months(chrono::Months::new(self.billing_cycle.months()))?;
+                Some((start, end))
+            });
+
+        let (immediate_charge, effective_at) = match period {
+            _ if is_downgrade => (zero, period.map_or(now, |(_, end)| end)),
+            Some((start, end)) => {
+                let current_price = self
+                    .price
+                    .money()
+                    .cloned()
+                    .or_else(|| {
+                        current
+                            .prices
+                            .get(&self.billing_cycle)
+                            .map(|price| price.amount.clone())
+                    })
+                    .unwrap_or(zero);
+                let total = (end - start).num_seconds().max(1);
+                let remaining = (end - now).num_seconds().clamp(0, total);
+                let unused = current_price.mul_ratio(remaining, total);
+                let due = if self.billing_cycle == target_cycle {
+                    new_period_price.mul_ratio(remaining, total)
+                } else {
+                    new_period_price.clone()
+                };
+                let charge =
+                    due.checked_sub(&unused)
+                        .ok_or_else(|| PlanChangeError::CurrencyMismatch {
+                            current: unused.currency.clone(),
+                            target: due.currency.clone(),
+                        })?;
+                (charge, now)
+            }
+            // Nothing paid up to credit: the new plan starts now, in full.
+            None => (new_period_price.clone(), now),
+        };
+
+        Ok(ChangePlanPreviewResponse {
+            tier: target.tier,
+            billing_cycle: target_cycle,
+            immediate_charge,
+            effective_at,
+            is_downgrade,
+            new_period_price,
+            monthly_credits_change,
+        })
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -114,14 +415,20 @@ pub struct OverdraftSettingsRequest {
 pub struct OverdraftSettingsResponse {
     pub enabled: bool,
     pub limit: i64,
+    /// Overdraft outstanding now.
     pub used: i64,
+    /// Overdraft drawn since the start of the calendar month, whether or not
+    /// it has been settled since. What the monthly spend cap applies to.
+    #[serde(default)]
+    pub spent_this_month: i64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SubscriptionInfo {
     pub tier: Tier,
     pub cycle: BillingCycle,
-    pub price: f64,
+    #[serde(flatten, with = "crate::money::major_price_and_currency")]
+    pub price: OptionalPrice,
     pub expires_at: Option<NaiveDateTime>,
     pub max_monthly_credits: i64,
     pub is_active: bool,
@@ -157,6 +464,9 @@ pub struct CreditsInfoResponse {
     /// [`TRIAL_PERIOD_DAYS`].
     #[serde(default)]
     pub trial_available: bool,
+    /// What makes up `extra_credits`, soonest to expire first.
+    #[serde(default)]
+    pub buckets: Vec<crate::credits::CreditBucket>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -202,6 +512,64 @@ pub struct ChangePlanRequest {
     pub billing_cycle: BillingCycle,
 }
 
+/// Same shape as [`ChangePlanRequest`], but nothing changes: the response
+/// says what confirming would cost.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChangePlanPreviewRequest {
+    pub tier: Tier,
+    pub billing_cycle: BillingCycle,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChangePlanPreviewResponse {
+    pub tier: Tier,
+    pub billing_cycle: BillingCycle,
+    /// Charged on confirming, after crediting the unused part of the current
+    /// period. Negative means the customer is left with a credit on their
+    /// account. Always zero for a downgrade.
+    pub immediate_charge: Money,
+    /// Now for an upgrade, the end of the paid period for a downgrade.
+    pub effective_at: DateTime<Utc>,
+    pub is_downgrade: bool,
+    /// What each period of the new plan costs once it is in effect.
+    pub new_period_price: Money,
+    /// Change to the monthly credit allowance. Negative means fewer credits.
+    pub monthly_credits_change: i64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlanChangeError {
+    /// The target is the plan the customer is already on.
+    SamePlan,
+    /// The target tier has no price for that cycle.
+    CycleNotOffered {
+        tier: Tier,
+        billing_cycle: BillingCycle,
+    },
+    /// The current and target prices are in different currencies, so the
+    /// unused period cannot be credited against the new one.
+    CurrencyMismatch { current: Currency, target: Currency },
+}
+
+impl std::fmt::Display for PlanChangeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlanChangeError::SamePlan => write!(f, "already on this plan"),
+            PlanChangeError::CycleNotOffered {
+                tier,
+                billing_cycle,
+            } => write!(f, "{} is not offered {}", tier, billing_cycle),
+            PlanChangeError::CurrencyMismatch { current, target } => write!(
+                f,
+                "current plan is billed in {}, new plan in {}",
+                current, target
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PlanChangeError {}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PaymentMethodResponse {
     pub last4: String,
@@ -232,33 +600,21 @@ pub struct PriceInfo {
     pub product_id: String,
     pub tier: Tier,
     pub billing_cycle: BillingCycle,
-    pub amount: i64, // Amount in cents
-    pub currency: String,
+    #[serde(flatten, with = "crate::money::amount_and_currency")]
+    pub amount: Money,
     pub payment_link: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FormattedPrice {
     pub price_id: String,
-    pub amount_cents: i64,
-    pub amount_display: f64, // Amount in major currency units (e.g., dollars)
-    pub currency: String,
+    #[serde(flatten, with = "crate::money::formatted_amount")]
+    pub amount: Money,
 }
 
 impl FormattedPrice {
     pub fn currency_symbol(&self) -> &str {
-        match self.currency.to_uppercase().as_str() {
-            "USD" => "$",
-            "EUR" => "€",
-            "GBP" => "£",
-            "JPY" => "¥",
-            "CAD" => "CA$",
-            "AUD" => "A$",
-            "CHF" => "CHF ",
-            "CNY" => "¥",
-            "INR" => "₹",
-            _ => &self.currency,
-        }
+        self.amount.currency.symbol()
     }
 }
 
@@ -348,31 +704,39 @@ impl Display for Tier {
     }
 }
 
-impl From<String> for Tier {
+impl FromStr for Tier {
+    type Err = ParseEnumError;
+
     // Legacy tier names still appear in stored subscription metadata.
     #[allow(deprecated)]
-    fn from(s: String) -> Self {
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_lowercase().as_str() {
-            "free" => Tier::Free,
-            "basic" => Tier::Basic,
-            "standard" => Tier::Standard,
-            "premium" => Tier::Premium,
-            _ => Tier::Free, // Default to Free for invalid values
+            "free" => Ok(Tier::Free),
+            "basic" => Ok(Tier::Basic),
+            "standard" => Ok(Tier::Standard),
+            "premium" => Ok(Tier::Premium),
+            _ => Err(ParseEnumError::new(
+                s,
+                "Tier",
+                &["free", "basic", "standard", "premium"],
+            )),
         }
     }
 }
 
-impl From<&str> for Tier {
-    // Legacy tier names still appear in stored subscription metadata.
-    #[allow(deprecated)]
-    fn from(s: &str) -> Self {
-        match s.to_lowercase().as_str() {
-            "free" => Tier::Free,
-            "basic" => Tier::Basic,
-            "standard" => Tier::Standard,
-            "premium" => Tier::Premium,
-            _ => Tier::Free, // Default to Free for invalid values
-        }
+impl TryFrom<&str> for Tier {
+    type Error = ParseEnumError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl TryFrom<String> for Tier {
+    type Error = ParseEnumError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
     }
 }
 
@@ -469,31 +833,62 @@ impl Display for BillingCycle {
     }
 }
 
-impl From<String> for BillingCycle {
-    fn from(s: String) -> Self {
+impl FromStr for BillingCycle {
+    type Err = ParseEnumError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_lowercase().as_str() {
-            "monthly" => BillingCycle::Monthly,
-            "quarterly" => BillingCycle::Quarterly,
-            "annual" => BillingCycle::Annual,
-            "triennial" => BillingCycle::Triennial,
-            _ => BillingCycle::Monthly, // Default to Monthly for invalid values
+            "monthly" => Ok(BillingCycle::Monthly),
+            "quarterly" => Ok(BillingCycle::Quarterly),
+            "annual" => Ok(BillingCycle::Annual),
+            "triennial" => Ok(BillingCycle::Triennial),
+            _ => Err(ParseEnumError::new(
+                s,
+                "BillingCycle",
+                &["monthly", "quarterly", "annual", "triennial"],
+            )),
         }
     }
 }
 
-impl From<&str> for BillingCycle {
-    fn from(s: &str) -> Self {
-        match s.to_lowercase().as_str() {
-            "monthly" => BillingCycle::Monthly,
-            "quarterly" => BillingCycle::Quarterly,
-            "annual" => BillingCycle::Annual,
-            "triennial" => BillingCycle::Triennial,
-            _ => BillingCycle::Monthly,
-        }
+impl TryFrom<&str> for BillingCycle {
+    type Error = ParseEnumError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl TryFrom<String> for BillingCycle {
+    type Error = ParseEnumError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl BillingCycle {
+    /// Parse, treating anything unrecognised as `Monthly`.
+    ///
+    /// Only for display paths that must render something. Anything that bills
+    /// or grants credits should parse strictly and handle the error, since a
+    /// guessed cycle charges the wrong amount.
+    pub fn parse_or_monthly(s: &str) -> Self {
+        s.parse().unwrap_or(BillingCycle::Monthly)
     }
 }
 
 impl Tier {
+    /// Parse, treating anything unrecognised as `Free`.
+    ///
+    /// This used to be what every conversion did, which meant one typo in
+    /// stored subscription metadata quietly downgraded a paying customer. Keep
+    /// it to places where showing *something* beats failing, and never use it
+    /// to decide what a customer is entitled to.
+    pub fn parse_or_free(s: &str) -> Self {
+        s.parse().unwrap_or(Tier::Free)
+    }
+
     /// Every tier that can still be held, sellable or not. Used for ranking and
     /// for resolving existing subscriptions, never for building a pricing page.
     #[allow(deprecated)]
@@ -569,14 +964,17 @@ pub struct ReferralInfo {
     /// referred user first subscribes, so this never returns to false even if
     /// they later cancel.
     pub rewarded: bool,
+    /// When the reward's [`crate::credits::CreditBucket`] expires, if it does.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reward_expires_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InvoiceInfo {
     pub id: String,
-    pub amount: i64,
-    pub currency: String,
-    pub status: String,
+    #[serde(flatten, with = "crate::money::amount_and_currency")]
+    pub amount: Money,
+    pub status: InvoiceStatus,
     pub created: String,
     pub hosted_invoice_url: Option<String>,
     pub invoice_pdf: Option<String>,
@@ -601,6 +999,223 @@ mod tests {
         assert!(!BillingCycle::Triennial.trial_eligible());
     }
 
+    #[test]
+    fn tiers_and_cycles_round_trip_through_display() {
+        for tier in Tier::all_tiers() {
+            assert_eq!(tier.to_string().parse::<Tier>(), Ok(tier));
+            assert_eq!(Tier::try_from(tier.to_string()), Ok(tier));
+        }
+        for cycle in BillingCycle::get_all_cycles() {
+            assert_eq!(cycle.to_string().parse::<BillingCycle>(), Ok(cycle));
+            assert_eq!(BillingCycle::try_from(cycle.to_string()), Ok(cycle));
+        }
+    }
+
+    #[test]
+    fn unknown_names_are_errors_unless_asked_to_default() {
+        let err = Tier::try_from("premuim").unwrap_err();
+        assert_eq!(err.input, "premuim");
+        assert_eq!(err.type_name, "Tier");
+        assert!(BillingCycle::try_from("yearly").is_err());
+
+        assert_eq!(Tier::parse_or_free("premuim"), Tier::Free);
+        assert_eq!(
+            BillingCycle::parse_or_monthly("yearly"),
+            BillingCycle::Monthly
+        );
+    }
+
+    #[test]
+    fn credits_round_to_the_nearest_penny() {
+        assert_eq!(credits_to_gbp(750_000), Money::gbp(100));
+        assert_eq!(credits_to_gbp(3_749), Money::gbp(0));
+        assert_eq!(credits_to_gbp(3_750), Money::gbp(1));
+        assert_eq!(credits_to_gbp(-3_750), Money::gbp(-1));
+        assert_eq!(format_credits_as_gbp(3_750_000), "£5");
+        assert_eq!(format_credits_as_gbp(375_000), "£0.50");
+        assert_eq!(format_credits(1_500_000), "1,500,000");
+        assert_eq!(format_credits(-999), "-999");
+        assert_eq!(format_credits(0), "0");
+    }
+
+    #[test]
+    fn formatted_price_keeps_its_wire_format() {
+        let json = serde_json::json!({
+            "price_id": "price_1",
+            "amount_cents": 1299,
+            "amount_display": 12.99,
+            "currency": "gbp",
+        });
+        let price: FormattedPrice = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(price.amount, Money::gbp(1299));
+        assert_eq!(price.currency_symbol(), "£");
+        assert_eq!(serde_json::to_value(&price).unwrap(), json);
+    }
+
+    fn plan(tier: Tier, monthly_credits: i64, prices: &[(BillingCycle, i64)]) -> TierPricing {
+        TierPricing {
+            tier,
+            prices: prices
+                .iter()
+                .map(|(cycle, pence)| {
+                    let price = FormattedPrice {
+                        price_id: format!("price_{}_{}", tier, cycle),
+                        amount: Money::gbp(*pence),
+                    };
+                    (*cycle, price)
+                })
+                .collect(),
+            monthly_credits: Some(monthly_credits),
+            feature_names: Vec::new(),
+        }
+    }
+
+    fn subscribed(tier: Tier, billing_cycle: BillingCycle, pence: i64) -> StripeSubscriptionInfo {
+        StripeSubscriptionInfo {
+            tier,
+            billing_cycle,
+            is_active: true,
+            price: OptionalPrice::Known(Money::gbp(pence)),
+            current_period_end: DateTime::parse_from_rfc3339("2026-03-01T00:00:00Z")
+                .ok()
+                .map(|end| end.with_timezone(&Utc)),
+            ..StripeSubscriptionInfo::free()
+        }
+    }
+
+    // Halfway through a February billing period.
+    fn mid_period() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2026-02-15T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn upgrades_credit_the_unused_half_period() {
+        let basic = plan(Tier::Basic, 100, &[(BillingCycle::Monthly, 1000)]);
+        let premium = plan(
+            Tier::Premium,
+            400,
+            &[(BillingCycle::Monthly, 3000), (BillingCycle::Annual, 30000)],
+        );
+        let current = subscribed(Tier::Basic, BillingCycle::Monthly, 1000);
+
+        // Same cycle: half of each price for the rest of the period.
+        let preview = current
+            .preview_change(&basic, &premium, BillingCycle::Monthly, mid_period())
+            .unwrap();
+        assert!(!preview.is_downgrade);
+        assert_eq!(preview.immediate_charge, Money::gbp(1000));
+        assert_eq!(preview.effective_at, mid_period());
+        assert_eq!(preview.new_period_price, Money::gbp(3000));
+        assert_eq!(preview.monthly_credits_change, 300);
+
+        // New cycle: a full year, less the unused half month.
+        let preview = current
+            .preview_change(&basic, &premium, BillingCycle::Annual, mid_period())
+            .unwrap();
+        assert_eq!(preview.immediate_charge, Money::gbp(29500));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn downgrades_wait_for_the_period_end() {
+        let basic = plan(Tier::Basic, 100, &[(BillingCycle::Monthly, 1000)]);
+        let premium = plan(
+            Tier::Premium,
+            400,
+            &[(BillingCycle::Monthly, 3000), (BillingCycle::Annual, 30000)],
+        );
+        let current = subscribed(Tier::Premium, BillingCycle::Annual, 30000);
+
+        for (target, cycle) in [
+            (&basic, BillingCycle::Monthly),
+            // Same tier, shorter commitment.
+            (&premium, BillingCycle::Monthly),
+        ] {
+            let preview = current
+                .preview_change(&premium, target, cycle, mid_period())
+                .unwrap();
+            assert!(preview.is_downgrade);
+            assert_eq!(preview.immediate_charge, Money::gbp(0));
+            assert_eq!(preview.effective_at, current.current_period_end.unwrap());
+        }
+        assert_eq!(
+            current
+                .preview_change(&premium, &basic, BillingCycle::Monthly, mid_period())
+                .unwrap()
+                .monthly_credits_change,
+            -300
+        );
+    }
+
+    #[test]
+    fn refuses_plans_that_cannot_be_previewed() {
+        let premium = plan(Tier::Premium, 400, &[(BillingCycle::Monthly, 3000)]);
+        let current = subscribed(Tier::Premium, BillingCycle::Monthly, 3000);
+        assert_eq!(
+            current.preview_change(&premium, &premium, BillingCycle::Monthly, mid_period()),
+            Err(PlanChangeError::SamePlan)
+        );
+        assert_eq!(
+            current.preview_change(&premium, &premium, BillingCycle::Triennial, mid_period()),
+            Err(PlanChangeError::CycleNotOffered {
+                tier: Tier::Premium,
+                billing_cycle: BillingCycle::Triennial
+            })
+        );
+    }
+
+    #[test]
+    fn statuses_keep_stripe_strings() {
+        for status in ["active", "past_due", "incomplete_expired", "inactive"] {
+            let parsed: SubscriptionStatus =
+                serde_json::from_value(serde_json::json!(status)).unwrap();
+            assert!(
+                !matches!(parsed, SubscriptionStatus::Unknown(_)),
+                "{}",
+                status
+            );
+            assert_eq!(
+                serde_json::to_value(&parsed).unwrap(),
+                serde_json::json!(status)
+            );
+        }
+        let future: SubscriptionStatus = serde_json::from_str("\"on_hold\"").unwrap();
+        assert_eq!(future, SubscriptionStatus::Unknown("on_hold".to_string()));
+        assert!(!future.grants_service() && !future.needs_payment_action());
+        assert_eq!(InvoiceStatus::from("paid"), InvoiceStatus::Paid);
+    }
+
+    #[test]
+    fn dunning_counts_down_the_retry_schedule() {
+        let failed = mid_period();
+        let day = |n: i64| Some(failed + chrono::Duration::days(n));
+
+        let first =
+            DunningSummary::new(SubscriptionStatus::PastDue, failed, 1, &PAYMENT_RETRY_DAYS)
+                .unwrap();
+        assert_eq!(first.attempts_remaining, 3);
+        assert_eq!(first.next_attempt_at, day(3));
+        assert_eq!(first.service_ends_at, day(15));
+
+        let last = DunningSummary::new(SubscriptionStatus::PastDue, failed, 4, &PAYMENT_RETRY_DAYS)
+            .unwrap();
+        assert_eq!(last.attempts_remaining, 0);
+        assert_eq!(last.next_attempt_at, None);
+
+        let stopped =
+            DunningSummary::new(SubscriptionStatus::Unpaid, failed, 4, &PAYMENT_RETRY_DAYS)
+                .unwrap();
+        assert_eq!(stopped.service_ends_at, None);
+
+        assert_eq!(
+            DunningSummary::new(SubscriptionStatus::Active, failed, 1, &PAYMENT_RETRY_DAYS),
+            None
+        );
+    }
+
     #[test]
     fn direction_follows_term_length_not_price() {
         // Triennial is the cheapest per month, so a price comparison would call
@@ -613,4 +1228,32 @@ mod tests {
         // Same cycle is not a downgrade.
         assert!(!BillingCycle::Annual.is_downgrade_to(BillingCycle::Annual));
     }
+
+    #[test]
+    fn subscription_info_reads_the_old_wire_format() {
+        let info: StripeSubscriptionInfo = serde_json::from_value(serde_json::json!({
+            "tier": "premium",
+            "billing_cycle": "monthly",
+            "status": "active",
+            "current_period_end": null,
+            "cancel_at_period_end": false,
+            "is_active": true,
+            "price": 999.0,
+            "currency": "gbp",
+            "payment_method_id": null
+        }))
+        .unwrap();
+        assert_eq!(info.price, OptionalPrice::Known(Money::gbp(999)));
+
+        let info: SubscriptionInfo = serde_json::from_value(serde_json::json!({
+            "tier": "premium",
+            "cycle": "annual",
+            "price": 99.99,
+            "expires_at": null,
+            "max_monthly_credits": 1000,
+            "is_active": true
+        }))
+        .unwrap();
+        assert_eq!(info.price, OptionalPrice::Unknown { currency: None });
+    }
 }
@@ -0,0 +1,85 @@
+//! Enums over a set of strings that another service owns and may extend.
+//!
+//! Stripe adds statuses and currencies, and the ledger adds transaction kinds,
+//! without telling this crate. [`open_string_enum!`] declares such an enum with
+//! one variant per known string and a catch-all that keeps anything else
+//! verbatim, so a payload with a new value still parses and round-trips.
+
+/// Declares the enum and generates, from one table of `Variant = "string"`
+/// pairs:
+///
+/// - the accessor named after `fn`, returning the string for any variant,
+/// - `From<&str>`, passing the input through `normalize` first if given,
+/// - `Display`, `Serialize` and `Deserialize`, all as that string.
+///
+/// The catch-all variant holds a `String` and is written first, since the
+/// macro cannot tell it apart from the known variants otherwise; the enum
+/// declares it last.
+macro_rules! open_string_enum {
+    (
+        $(#[$attr:meta])*
+        $vis:vis enum $name:ident {
+            $(#[$other_attr:meta])*
+            $other:ident(String),
+            $(
+                $(#[$variant_attr:meta])*
+                $variant:ident = $value:literal,
+            )*
+        }
+        fn $as_str:ident;
+        $(normalize = $normalize:path;)?
+    ) => {
+        $(#[$attr])*
+        $vis enum $name {
+            $(
+                $(#[$variant_attr])*
+                $variant,
+            )*
+            $(#[$other_attr])*
+            $other(String),
+        }
+
+        impl $name {
+            pub fn $as_str(&self) -> &str {
+                match self {
+                    $($name::$variant => $value,)*
+                    $name::$other(value) => value,
+                }
+            }
+        }
+
+        impl From<&str> for $name {
+            fn from(s: &str) -> Self {
+                $(
+                    let normalized = $normalize(s);
+                    let s = normalized.as_str();
+                )?
+                match s {
+                    $($value => $name::$variant,)*
+                    other => $name::$other(other.to_string()),
+                }
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{}", self.$as_str())
+            }
+        }
+
+        impl serde::Serialize for $name {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(self.$as_str())
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $name {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let value = <String as serde::Deserialize>::deserialize(deserializer)?;
+                Ok($name::from(value.as_str()))
+            }
+        }
+    };
+}
+
+pub(crate) use open_string_enum;
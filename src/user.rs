@@ -50,7 +50,7 @@ pub struct ApplyReferralCodeResponse {
 pub struct AccountDeletionCheckResponse {
     pub can_delete: bool,
     pub reason: Option<String>,
-    pub subscription_status: Option<String>,
+    pub subscription_status: Option<crate::pricing::SubscriptionStatus>,
 }
 
 /// One item in the onboarding checklist. `complete` is server-derived from
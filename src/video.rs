@@ -0,0 +1,395 @@
+//! Reference implementation of the frame deduplication contract.
+//!
+//! The numbers live in [`crate::moderate`]; this is the arithmetic they apply
+//! to. Every step is integer-only and fully specified, so a port to another
+//! language can be checked against the golden vector here
+//! ([`golden_diagonal_rgb`] and [`GOLDEN_DIAGONAL_SIGNATURE`]) rather than
+//! against whichever image library it happens to use.
+
+use crate::moderate::{
+    MAX_VIDEO_FRAMES, VIDEO_COLOR_THRESHOLD, VIDEO_DHASH_THRESHOLD, VIDEO_SAMPLE_INTERVAL_MS,
+};
+use serde::{Deserialize, Serialize};
+
+/// Columns and rows of the luminance grid a difference hash is read from. One
+/// more column than bits per row, since each bit compares a cell with its right
+/// neighbour.
+const DHASH_COLS: usize = 9;
+const DHASH_ROWS: usize = 8;
+
+/// Side of the mean-colour grid.
+const COLOR_GRID: usize = 4;
+
+pub const GOLDEN_DIAGONAL_WIDTH: u32 = 36;
+pub const GOLDEN_DIAGONAL_HEIGHT: u32 = 24;
+
+/// The signature every port must produce for [`golden_diagonal_rgb`]. A port
+/// that disagrees will bill the same clip differently.
+pub const GOLDEN_DIAGONAL_SIGNATURE: FrameSignature = FrameSignature {
+    dhash: 0x0103_0418_30d0_8021,
+    colors: [
+        [60, 24, 10],
+        [123, 51, 32],
+        [186, 78, 55],
+        [140, 105, 77],
+        [138, 54, 34],
+        [196, 81, 110],
+        [103, 108, 168],
+        [71, 135, 126],
+        [188, 84, 58],
+        [80, 111, 169],
+        [86, 138, 96],
+        [149, 165, 146],
+        [66, 114, 82],
+        [101, 141, 129],
+        [164, 168, 147],
+        [184, 195, 118],
+    ],
+};
+
+/// The golden frame, `GOLDEN_DIAGONAL_WIDTH` by `GOLDEN_DIAGONAL_HEIGHT`
+/// packed RGB8. Pixel `(x, y)` is
+/// `[(7x + 13y) % 256, (3x + 5y) % 256, (x * y) % 256]`, which gives every
+/// cell of both grids a different value.
+pub fn golden_diagonal_rgb() -> Vec<u8> {
+    let mut data = Vec::new();
+    for y in 0..GOLDEN_DIAGONAL_HEIGHT {
+        for x in 0..GOLDEN_DIAGONAL_WIDTH {
+            data.extend_from_slice(&[
+                ((x * 7 + y * 13) % 256) as u8,
+                ((x * 3 + y * 5) % 256) as u8,
+                ((x * y) % 256) as u8,
+            ]);
+        }
+    }
+    data
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameError {
+    /// The frame is smaller than the 9x8 grid a difference hash needs.
+    TooSmall { width: u32, height: u32 },
+    /// The buffer is not `width * height * 3` bytes of packed RGB.
+    BufferSize { expected: usize, actual: usize },
+    /// `width * height * 3` does not fit in a `usize`.
+    TooLarge { width: u32, height: u32 },
+}
+
+impl std::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameError::TooSmall { width, height } => {
+                write!(f, "frame {}x{} is smaller than 9x8", width, height)
+            }
+            FrameError::BufferSize { expected, actual } => {
+                write!(f, "expected {} bytes of RGB, got {}", expected, actual)
+            }
+            FrameError::TooLarge { width, height } => {
+                write!(f, "frame {}x{} is too large to address", width, height)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+/// What dedup compares two frames by.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameSignature {
+    /// Row-major, most significant bit first: bit `63 - (row * 8 + col)` is set
+    /// when cell `col` of that row is brighter than cell `col + 1`.
+    pub dhash: u64,
+    /// Mean RGB of each cell of a 4x4 grid, row-major.
+    pub colors: [[u8; 3]; 16],
+}
+
+impl FrameSignature {
+    /// Signature of a decoded frame, packed RGB8 with no row padding.
+    ///
+    /// Grids are built by area averaging: cell `i` of `n` along an axis of
+    /// length `len` covers pixels `i * len / n` up to (not including)
+    /// `(i + 1) * len / n`, and its value is the floored mean of those pixels.
+    /// Luminance is the floored ITU-R BT.601 weighting
+    /// `(299 * r + 587 * g + 114 * b) / 1000`, taken per pixel before averaging.
+    pub fn from_rgb(width: u32, height: u32, rgb: &[u8]) -> Result<Self, FrameError> {
+        if (width as usize) < DHASH_COLS || (height as usize) < DHASH_ROWS {
+            return Err(FrameError::TooSmall { width, height });
+        }
+        let expected = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|pixels| pixels.checked_mul(3))
+            .ok_or(FrameError::TooLarge { width, height })?;
+        if rgb.len() != expected {
+            return Err(FrameError::BufferSize {
+                expected,
+                actual: rgb.len(),
+            });
+        }
+
+        let frame = Rgb {
+            width: width as usize,
+            height: height as usize,
+            data: rgb,
+        };
+
+        let mut dhash = 0u64;
+        for row in 0..DHASH_ROWS {
+            let luma: Vec<u32> = (0..DHASH_COLS)
+                .map(|col| frame.cell_mean(col, row, DHASH_COLS, DHASH_ROWS, luminance))
+                .collect();
+            for pair in luma.windows(2) {
+                dhash = (dhash << 1) | u64::from(pair[0] > pair[1]);
+            }
+        }
+
+        let mut colors = [[0u8; 3]; 16];
+        for (i, cell) in colors.iter_mut().enumerate() {
+            let (col, row) = (i % COLOR_GRID, i / COLOR_GRID);
+            for (channel, value) in cell.iter_mut().enumerate() {
+                *value = frame.cell_mean(col, row, COLOR_GRID, COLOR_GRID, |px| {
+                    u32::from(px[channel])
+                }) as u8;
+            }
+        }
+
+        Ok(Self { dhash, colors })
+    }
+}
+
+struct Rgb<'a> {
+    width: usize,
+    height: usize,
+    data: &'a [u8],
+}
+
+impl Rgb<'_> {
+    fn cell_mean(
+        &self,
+        col: usize,
+        row: usize,
+        cols: usize,
+        rows: usize,
+        value: impl Fn(&[u8]) -> u32,
+    ) -> u32 {
+        let (x0, x1) = (col * self.width / cols, (col + 1) * self.width / cols);
+        let (y0, y1) = (row * self.height / rows, (row + 1) * self.height / rows);
+        let mut sum = 0u64;
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let offset = (y * self.width + x) * 3;
+                sum += u64::from(value(&self.data[offset..offset + 3]));
+            }
+        }
+        (sum / ((x1 - x0) * (y1 - y0)) as u64) as u32
+    }
+}
+
+fn luminance(px: &[u8]) -> u32 {
+    (299 * u32::from(px[0]) + 587 * u32::from(px[1]) + 114 * u32::from(px[2])) / 1000
+}
+
+/// Whether two frames show the same shot: hashes within
+/// [`VIDEO_DHASH_THRESHOLD`] bits *and* every channel of every colour cell
+/// within [`VIDEO_COLOR_THRESHOLD`].
+pub fn is_same_shot(a: &FrameSignature, b: &FrameSignature) -> bool {
+    if (a.dhash ^ b.dhash).count_ones() > VIDEO_DHASH_THRESHOLD {
+        return false;
+    }
+    a.colors.iter().zip(b.colors.iter()).all(|(x, y)| {
+        x.iter()
+            .zip(y.iter())
+            .all(|(p, q)| u32::from(p.abs_diff(*q)) <= VIDEO_COLOR_THRESHOLD)
+    })
+}
+
+/// A candidate frame pulled at `timestamp_ms`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SampledFrame {
+    pub timestamp_ms: i64,
+    pub signature: FrameSignature,
+}
+
+/// Timestamps to pull candidate frames at: every
+/// [`VIDEO_SAMPLE_INTERVAL_MS`] from the start, while still inside the clip.
+pub fn sample_timestamps(duration_ms: i64) -> Vec<i64> {
+    (0..duration_ms.max(0))
+        .step_by(VIDEO_SAMPLE_INTERVAL_MS as usize)
+        .collect()
+}
+
+/// Timestamps of the frames that survive dedup, in order.
+///
+/// Frames are taken in timestamp order and each is compared with the last one
+/// kept, not the one just before it, so a slow pan still produces a new frame
+/// once it has drifted far enough from the last kept one. The first frame is
+/// always kept. Only the first [`MAX_VIDEO_FRAMES`] survivors are returned,
+/// since no more than that are moderated.
+pub fn dedup_frames(frames: &[SampledFrame]) -> Vec<i64> {
+    let mut ordered: Vec<&SampledFrame> = frames.iter().collect();
+    ordered.sort_by_key(|frame| frame.timestamp_ms);
+
+    let mut kept: Vec<&SampledFrame> = Vec::new();
+    for frame in ordered {
+        match kept.last() {
+            Some(last) if is_same_shot(&last.signature, &frame.signature) => {}
+            _ => kept.push(frame),
+        }
+    }
+    kept.into_iter()
+        .take(MAX_VIDEO_FRAMES)
+        .map(|frame| frame.timestamp_ms)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const W: u32 = 36;
+    const H: u32 = 24;
+
+    fn render(pixel: impl Fn(u32, u32) -> [u8; 3]) -> Vec<u8> {
+        let mut data = Vec::with_capacity((W * H * 3) as usize);
+        for y in 0..H {
+            for x in 0..W {
+                data.extend_from_slice(&pixel(x, y));
+            }
+        }
+        data
+    }
+
+    fn signature(pixel: impl Fn(u32, u32) -> [u8; 3]) -> FrameSignature {
+        FrameSignature::from_rgb(W, H, &render(pixel)).unwrap()
+    }
+
+    fn brightening(x: u32, _y: u32) -> [u8; 3] {
+        let v = (x * 7) as u8;
+        [v, v, v]
+    }
+
+    fn darkening(x: u32, _y: u32) -> [u8; 3] {
+        let v = 255 - (x * 7) as u8;
+        [v, v, v]
+    }
+
+    #[test]
+    fn golden_dhashes() {
+        assert_eq!(signature(brightening).dhash, 0);
+        assert_eq!(signature(darkening).dhash, u64::MAX);
+        assert_eq!(signature(|_, _| [200, 10, 10]).dhash, 0);
+    }
+
+    #[test]
+    fn golden_diagonal_signature() {
+        let rgb = golden_diagonal_rgb();
+        assert_eq!(
+            FrameSignature::from_rgb(GOLDEN_DIAGONAL_WIDTH, GOLDEN_DIAGONAL_HEIGHT, &rgb),
+            Ok(GOLDEN_DIAGONAL_SIGNATURE)
+        );
+    }
+
+    #[test]
+    fn golden_color_grid() {
+        assert_eq!(signature(|_, _| [200, 10, 10]).colors, [[200, 10, 10]; 16]);
+    }
+
+    #[test]
+    fn flat_scenes_of_different_colours_are_different_shots() {
+        // Both hash to zero: a flat frame has no gradients to read.
+        let red = signature(|_, _| [200, 10, 10]);
+        let blue = signature(|_, _| [10, 10, 200]);
+        assert_eq!(red.dhash, blue.dhash);
+        assert!(!is_same_shot(&red, &blue));
+    }
+
+    #[test]
+    fn thresholds_are_inclusive() {
+        let base = signature(|_, _| [100, 100, 100]);
+        let mut near = base.clone();
+        near.dhash = 0b11_1111;
+        near.colors[5] = [124, 76, 100];
+        assert!(is_same_shot(&base, &near));
+
+        near.dhash = 0b111_1111;
+        assert!(!is_same_shot(&base, &near));
+
+        near.dhash = 0;
+        near.colors[5] = [125, 100, 100];
+        assert!(!is_same_shot(&base, &near));
+    }
+
+    #[test]
+    fn golden_dedup() {
+        let shots = [
+            signature(brightening),
+            signature(brightening),
+            signature(|x, y| {
+                // The same gradient with mild noise.
+                let [r, g, b] = brightening(x, y);
+                let n = ((x + y) % 3) as u8;
+                [r.saturating_add(n), g, b]
+            }),
+            signature(|_, _| [200, 10, 10]),
+            signature(|_, _| [10, 10, 200]),
+            signature(|_, _| [12, 14, 190]),
+            signature(brightening),
+        ];
+        let frames: Vec<SampledFrame> = sample_timestamps(3500)
+            .into_iter()
+            .zip(shots)
+            .map(|(timestamp_ms, signature)| SampledFrame {
+                timestamp_ms,
+                signature,
+            })
+            .collect();
+        assert_eq!(dedup_frames(&frames), vec![0, 1500, 2000, 3000]);
+    }
+
+    #[test]
+    fn dedup_keeps_at_most_max_video_frames() {
+        let frames: Vec<SampledFrame> = (0..MAX_VIDEO_FRAMES as i64 + 5)
+            .map(|i| SampledFrame {
+                timestamp_ms: i * i64::from(VIDEO_SAMPLE_INTERVAL_MS),
+                signature: signature(|_, _| {
+                    let v = if i % 2 == 0 { 0 } else { 200 };
+                    [v, v, v]
+                }),
+            })
+            .collect();
+        let kept = dedup_frames(&frames);
+        assert_eq!(kept.len(), MAX_VIDEO_FRAMES);
+        assert_eq!(kept.last(), Some(&((MAX_VIDEO_FRAMES as i64 - 1) * 500)));
+    }
+
+    #[test]
+    fn samples_every_interval_inside_the_clip() {
+        assert_eq!(sample_timestamps(1500), vec![0, 500, 1000]);
+        assert_eq!(sample_timestamps(1501), vec![0, 500, 1000, 1500]);
+        assert!(sample_timestamps(0).is_empty());
+    }
+
+    #[test]
+    fn rejects_bad_buffers() {
+        assert_eq!(
+            FrameSignature::from_rgb(8, 8, &[0; 8 * 8 * 3]),
+            Err(FrameError::TooSmall {
+                width: 8,
+                height: 8
+            })
+        );
+        assert_eq!(
+            FrameSignature::from_rgb(9, 8, &[0; 10]),
+            Err(FrameError::BufferSize {
+                expected: 216,
+                actual: 10
+            })
+        );
+        assert_eq!(
+            FrameSignature::from_rgb(u32::MAX, u32::MAX, &[]),
+            Err(FrameError::TooLarge {
+                width: u32::MAX,
+                height: u32::MAX
+            })
+        );
+    }
+}
@@ -0,0 +1,431 @@
+//! The matcher behind [`GuildConfig::custom_word_filters`].
+//!
+//! Patterns match case-insensitively on word boundaries, where a word is a run
+//! of Unicode letters and digits: anything else, punctuation and underscores
+//! included, separates words. A leading or trailing `*` drops the boundary on
+//! that side, so `spoiler*` also catches "spoilers". Spans are byte offsets
+//! into the text as given, never into a lowercased copy, because lowercasing
+//! can change a character's length.
+//!
+//! [`WordFilter::find_matches_normalized`] matches against the
+//! [`crate::normalize`] skeleton instead, so `b4d w\u{200B}ord` still hits
+//! `bad word`.
+
+use crate::discord::GuildConfig;
+use crate::normalize::{NormalizeOptions, skeleton};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WordFilterError {
+    /// Nothing left to match once whitespace and wildcards are removed, or
+    /// once normalization drops invisible and combining characters.
+    EmptyPattern { pattern: String },
+    /// A `*` anywhere but the first or last character.
+    InteriorWildcard { pattern: String },
+}
+
+impl std::fmt::Display for WordFilterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WordFilterError::EmptyPattern { pattern } => {
+                write!(f, "word filter \"{}\" is empty", pattern)
+            }
+            WordFilterError::InteriorWildcard { pattern } => write!(
+                f,
+                "word filter \"{}\" has a `*` in the middle; only a leading or trailing `*` is allowed",
+                pattern
+            ),
+        }
+    }
+}
+
+impl std::error::Error for WordFilterError {}
+
+/// One hit. `start..end` is a byte range of the original text.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WordMatch {
+    /// The filter entry that matched, as configured.
+    pub pattern: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Pattern {
+    source: String,
+    needle: Vec<char>,
+    /// `needle` after the same normalization as the text it is matched
+    /// against, so `h3llo` as a pattern still matches.
+    skeleton_needle: Vec<char>,
+    open_start: bool,
+    open_end: bool,
+}
+
+/// A compiled set of word filters. Build once per config change, not per
+/// message.
+#[derive(Debug, Clone, Default)]
+pub struct WordFilter {
+    patterns: Vec<Pattern>,
+}
+
+/// Check a filter entry the way the dashboard should before saving it.
+pub fn validate_pattern(pattern: &str) -> Result<(), WordFilterError> {
+    compile(pattern).map(|_| ())
+}
+
+fn compile(pattern: &str) -> Result<Pattern, WordFilterError> {
+    let trimmed = pattern.trim();
+    let (open_start, rest) = match trimmed.strip_prefix('*') {
+        Some(rest) => (true, rest),
+        None => (false, trimmed),
+    };
+    let (open_end, core) = match rest.strip_suffix('*') {
+        Some(core) => (true, core),
+        None => (false, rest),
+    };
+    let core = core.trim();
+    if core.is_empty() {
+        return Err(WordFilterError::EmptyPattern {
+            pattern: pattern.to_string(),
+        });
+    }
+    if core.contains('*') {
+        return Err(WordFilterError::InteriorWildcard {
+            pattern: pattern.to_string(),
+        });
+    }
+    let skeleton_needle: Vec<char> = skeleton(core, &NormalizeOptions::words())
+        .text
+        .chars()
+        .collect();
+    if skeleton_needle.is_empty() {
+        return Err(WordFilterError::EmptyPattern {
+            pattern: pattern.to_string(),
+        });
+    }
+    Ok(Pattern {
+        source: pattern.to_string(),
+        needle: core.chars().flat_map(char::to_lowercase).collect(),
+        skeleton_needle,
+        open_start,
+        open_end,
+    })
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric()
+}
+
+/// A lowercased character and the byte range of the original character it
+/// came from. `first` marks the first of a multi-character lowercasing, the
+/// only place a match may start.
+struct Folded {
+    c: char,
+    start: usize,
+    end: usize,
+    first: bool,
+}
+
+impl WordFilter {
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Result<Self, WordFilterError> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| compile(pattern.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { patterns })
+    }
+
+    /// The guild's filter, or one that matches nothing when the word filter is
+    /// switched off.
+    ///
+    /// Stored entries may predate [`validate_pattern`], so one the validator
+    /// rejects is left out rather than failing the whole filter. The rejected
+    /// entries are returned alongside for the caller to log or show.
+    /// [`WordFilter::new`] stays strict, for checking entries before they are
+    /// saved.
+    pub fn from_config(config: &GuildConfig) -> (Self, Vec<WordFilterError>) {
+        if !config.enable_word_filter {
+            return (Self::default(), Vec::new());
+        }
+        let mut patterns = Vec::new();
+        let mut rejected = Vec::new();
+        for pattern in &config.custom_word_filters {
+            match compile(pattern) {
+                Ok(pattern) => patterns.push(pattern),
+                Err(err) => rejected.push(err),
+            }
+        }
+        (Self { patterns }, rejected)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Every match of every pattern, ordered by position. A pattern's own
+    /// matches never overlap; matches of different patterns may.
+    pub fn find_matches(&self, text: &str) -> Vec<WordMatch> {
+        self.find(text, |pattern| &pattern.needle)
+    }
+
+    /// Like [`WordFilter::find_matches`], but matched against the normalized
+    /// skeleton of `text`. Spans still point into `text` itself, and cover any
+    /// invisible characters hidden inside the match.
+    pub fn find_matches_normalized(&self, text: &str) -> Vec<WordMatch> {
+        let skeleton = skeleton(text, &NormalizeOptions::words());
+        self.find(&skeleton.text, |pattern| &pattern.skeleton_needle)
+            .into_iter()
+            .map(|m| {
+                let original = skeleton.original_range(m.start..m.end);
+                WordMatch {
+                    pattern: m.pattern,
+                    start: original.start,
+                    end: original.end,
+                }
+            })
+            .collect()
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        !self.find_matches(text).is_empty()
+    }
+
+    fn find(&self, text: &str, needle: impl Fn(&Pattern) -> &[char]) -> Vec<WordMatch> {
+        let folded: Vec<Folded> = text
+            .char_indices()
+            .flat_map(|(start, c)| {
+                let end = start + c.len_utf8();
+                c.to_lowercase().enumerate().map(move |(i, lower)| Folded {
+                    c: lower,
+                    start,
+                    end,
+                    first: i == 0,
+                })
+            })
+            .collect();
+
+        let mut matches = Vec::new();
+        for pattern in &self.patterns {
+            let needle = needle(pattern);
+            let mut i = 0;
+            while i + needle.len() <= folded.len() {
+                if let Some(m) = Self::match_at(text, &folded, pattern, needle, i) {
+                    matches.push(m);
+                    i += needle.len();
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        matches.sort_by_key(|m| (m.start, m.end));
+        matches
+    }
+
+    fn match_at(
+        text: &str,
+        folded: &[Folded],
+        pattern: &Pattern,
+        needle: &[char],
+        i: usize,
+    ) -> Option<WordMatch> {
+        let j = i + needle.len();
+        if !folded[i].first || folded.get(j).is_some_and(|next| !next.first) {
+            return None;
+        }
+        if !folded[i..j].iter().zip(needle).all(|(f, c)| f.c == *c) {
+            return None;
+        }
+        let (start, end) = (folded[i].start, folded[j - 1].end);
+        if !pattern.open_start && text[..start].chars().next_back().is_some_and(is_word_char) {
+            return None;
+        }
+        if !pattern.open_end && text[end..].chars().next().is_some_and(is_word_char) {
+            return None;
+        }
+        Some(WordMatch {
+            pattern: pattern.source.clone(),
+            start,
+            end,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (pattern, text, matched substrings)
+    const CASES: &[(&str, &str, &[&str])] = &[
+        // Whole words only.
+        ("cat", "cat", &["cat"]),
+        ("cat", "the cat sat", &["cat"]),
+        ("cat", "concatenate", &[]),
+        ("cat", "cats", &[]),
+        ("cat", "CAT Cat cAt", &["CAT", "Cat", "cAt"]),
+        // Wildcards open one side each.
+        ("cat*", "cats catalog", &["cat", "cat"]),
+        ("cat*", "bobcat", &[]),
+        ("*cat", "bobcat cats", &["cat"]),
+        ("*cat*", "concatenate", &["cat"]),
+        // Punctuation and underscores separate words; digits do not.
+        (
+            "cat",
+            "cat, cat. (cat) \"cat\"",
+            &["cat", "cat", "cat", "cat"],
+        ),
+        ("cat", "cat's", &["cat"]),
+        ("cat", "cat_cat", &["cat", "cat"]),
+        ("cat", "cat-cat", &["cat", "cat"]),
+        ("cat", "cat1 2cat", &[]),
+        // Phrases match exactly, spaces included.
+        ("bad word", "a bad word here", &["bad word"]),
+        ("bad word", "a bad  word", &[]),
+        // Unicode letters are word characters, and case folds across scripts.
+        ("кот", "КОТ котик", &["КОТ"]),
+        ("cat", "écat caté", &[]),
+        ("straße", "STRASSE Straße", &["Straße"]),
+        ("ß", "ẞ", &["ẞ"]),
+        // Non-letters in a pattern still need non-word neighbours.
+        ("$$$", "win $$$ now", &["$$$"]),
+        ("$$$", "a$$$", &[]),
+        // Emoji are not word characters.
+        ("cat", "🐱cat🐱", &["cat"]),
+    ];
+
+    #[test]
+    fn boundary_rules() {
+        for (pattern, text, expected) in CASES {
+            let filter = WordFilter::new(&[*pattern]).unwrap();
+            let found: Vec<&str> = filter
+                .find_matches(text)
+                .iter()
+                .map(|m| &text[m.start..m.end])
+                .collect();
+            assert_eq!(&found, expected, "{:?} in {:?}", pattern, text);
+        }
+    }
+
+    #[test]
+    fn spans_point_into_the_original_text() {
+        // 'İ' lowercases to two characters, shifting every later offset in a
+        // lowercased copy.
+        let text = "İİ cat";
+        let matches = WordFilter::new(&["cat"]).unwrap().find_matches(text);
+        assert_eq!(
+            matches,
+            vec![WordMatch {
+                pattern: "cat".to_string(),
+                start: 5,
+                end: 8
+            }]
+        );
+        assert_eq!(&text[5..8], "cat");
+    }
+
+    #[test]
+    fn validation() {
+        for ok in ["cat", " cat ", "*cat", "cat*", "*cat*", "bad word"] {
+            assert_eq!(validate_pattern(ok), Ok(()), "{:?}", ok);
+        }
+        for empty in [
+            "",
+            "   ",
+            "*",
+            "**",
+            "* *",
+            "\u{200B}",
+            "\u{0301}",
+            "*\u{200B}*",
+        ] {
+            assert!(
+                matches!(
+                    validate_pattern(empty),
+                    Err(WordFilterError::EmptyPattern { .. })
+                ),
+                "{:?}",
+                empty
+            );
+        }
+        for interior in ["c*t", "***cat", "cat**"] {
+            assert!(
+                matches!(
+                    validate_pattern(interior),
+                    Err(WordFilterError::InteriorWildcard { .. })
+                ),
+                "{:?}",
+                interior
+            );
+        }
+    }
+
+    #[test]
+    fn disabled_config_matches_nothing() {
+        let mut config = GuildConfig {
+            custom_word_filters: vec!["cat".to_string()],
+            ..Default::default()
+        };
+        assert!(WordFilter::from_config(&config).0.is_empty());
+        config.enable_word_filter = true;
+        assert!(WordFilter::from_config(&config).0.is_match("a cat"));
+    }
+
+    #[test]
+    fn stored_entries_the_validator_rejects_are_skipped() {
+        let config = GuildConfig {
+            enable_word_filter: true,
+            custom_word_filters: vec!["c*t".to_string(), "dog".to_string(), "*".to_string()],
+            ..Default::default()
+        };
+        let (filter, rejected) = WordFilter::from_config(&config);
+        assert!(filter.is_match("a dog"));
+        assert!(!filter.is_match("a cat"));
+        assert_eq!(
+            rejected,
+            vec![
+                WordFilterError::InteriorWildcard {
+                    pattern: "c*t".to_string()
+                },
+                WordFilterError::EmptyPattern {
+                    pattern: "*".to_string()
+                },
+            ]
+        );
+        assert!(WordFilter::new(&config.custom_word_filters).is_err());
+    }
+
+    #[test]
+    fn space_inside_a_wildcard_is_trimmed() {
+        let filter = WordFilter::new(&["* cat", "dog *"]).unwrap();
+        let text = "bobcat dogs";
+        let found: Vec<&str> = filter
+            .find_matches(text)
+            .iter()
+            .map(|m| &text[m.start..m.end])
+            .collect();
+        assert_eq!(found, vec!["cat", "dog"]);
+    }
+
+    #[test]
+    fn normalized_matching_sees_through_obfuscation() {
+        let filter = WordFilter::new(&["bad word", "h3llo*"]).unwrap();
+        let text = "so b4d w\u{200B}\u{043E}rd, HELL0 there, h e l l o s";
+        let found: Vec<&str> = filter
+            .find_matches_normalized(text)
+            .iter()
+            .map(|m| &text[m.start..m.end])
+            .collect();
+        assert_eq!(found, vec!["b4d w\u{200B}\u{043E}rd", "HELL0", "h e l l o"]);
+        assert!(filter.find_matches(text).is_empty());
+    }
+
+    #[test]
+    fn matches_from_several_patterns_are_ordered() {
+        let filter = WordFilter::new(&["world", "hello"]).unwrap();
+        let patterns: Vec<String> = filter
+            .find_matches("hello world hello")
+            .into_iter()
+            .map(|m| m.pattern)
+            .collect();
+        assert_eq!(patterns, vec!["hello", "world", "hello"]);
+    }
+}